@@ -5,6 +5,7 @@ use std::{
     ffi::OsString,
     fs::File,
     io::{stdout, Read, Write},
+    mem,
     process,
 };
 
@@ -15,7 +16,7 @@ use nix::unistd::{fork, ForkResult};
 use wl_clipboard_rs::{
     copy::{self, ServeRequests, Source},
     paste::{self, get_contents},
-    utils::is_text,
+    utils::{is_text, trim_trailing_newline},
 };
 
 #[derive(Clone, Copy, Eq, PartialEq)]
@@ -250,9 +251,8 @@ fn main() -> Result<(), ExitFailure> {
             .context("Couldn't read clipboard contents")?;
 
         // Remove the last newline character if needed.
-        let last_character_is_newline = contents.last().map(|&c| c == b'\n').unwrap_or(false);
-        if options.rmlastnl && is_text(&mime_type) && last_character_is_newline {
-            contents.pop();
+        if options.rmlastnl && is_text(&mime_type) {
+            trim_trailing_newline(&mut contents);
         }
 
         // Write everything to stdout.
@@ -299,6 +299,9 @@ fn main() -> Result<(), ExitFailure> {
             // TODO: is there any way to verify that we don't spawn any threads?
             if let ForkResult::Child = fork().unwrap() {
                 drop(prepared_copy.serve());
+            } else {
+                // The temp files are now owned by the child, don't let the parent remove them.
+                mem::forget(prepared_copy);
             }
         }
     }
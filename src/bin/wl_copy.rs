@@ -1,12 +1,44 @@
 #![deny(unsafe_code)]
 
-use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+use std::{
+    ffi::OsString,
+    fs,
+    io::{stdout, Read, Write},
+    mem,
+    os::unix::{
+        ffi::OsStringExt,
+        io::{AsRawFd, RawFd},
+    },
+    path::PathBuf,
+    process,
+    time::Duration,
+};
 
 use exitfailure::ExitFailure;
-use nix::unistd::{fork, ForkResult};
+use failure::{Fail, ResultExt};
+use libc::STDIN_FILENO;
+use log::warn;
+use nix::{
+    errno::Errno,
+    fcntl::{fcntl, FcntlArg, OFlag},
+    sys::{
+        signal::{SigSet, Signal},
+        signalfd::{SfdFlags, SignalFd},
+    },
+    unistd::{close, dup2, fork, ForkResult, Pid},
+};
 use structopt::{clap::AppSettings, StructOpt};
 
-use wl_clipboard_rs::copy::{self, clear, ClipboardType, MimeType, Seat, ServeRequests, Source};
+use wl_clipboard_rs::{
+    connection::Connection,
+    copy::{self, clear, ClipboardType, MimeSource, MimeType, Seat, ServeRequests, Source},
+    paste,
+    utils::{
+        get_seat_names_with_connection, is_primary_selection_supported,
+        is_primary_selection_supported_with_connection, mime_from_extension, uri_list,
+        PrimarySelectionCheckError,
+    },
+};
 
 #[derive(StructOpt)]
 #[structopt(name = "wl-copy",
@@ -20,61 +52,336 @@ struct Options {
     /// copying e.g. sensitive data, like passwords. Note however that certain apps may have issues
     /// pasting when this option is used, in particular XWayland clients are known to suffer from
     /// this.
+    ///
+    /// Together with --foreground, the standard input is passed straight to the pasting client
+    /// rather than read up front, unless the data needs to be inspected first (MIME type
+    /// autodetection, newline trimming, HTML).
     #[structopt(long, short = "o", conflicts_with = "clear")]
     paste_once: bool,
 
     /// Stay in the foreground instead of forking
+    ///
+    /// In the foreground, SIGTERM makes wl-copy give up the clipboard, remove its temporary files
+    /// and exit cleanly, which suits running it as a service.
     #[structopt(long, short, conflicts_with = "clear")]
     foreground: bool,
 
+    /// Print the PID of the serving process once the clipboard contents are set
+    ///
+    /// The PID followed by a newline is printed to the standard output as soon as the compositor
+    /// has received the new clipboard contents. Scripts running wl-copy --foreground in the
+    /// background can wait for this line instead of sleeping.
+    #[structopt(long, conflicts_with = "clear")]
+    print_ready: bool,
+
     /// Clear the clipboard instead of copying
+    ///
+    /// Copying replaces the previous clipboard contents right away, so running wl-copy --clear
+    /// before copying is unnecessary. It leaves the clipboard empty for a moment, which paste UIs
+    /// can pick up.
     #[structopt(long, short)]
     clear: bool,
 
+    /// Pick the clipboard to use
+    ///
+    /// The "primary" clipboard, and so "both", requires the compositor to support the
+    /// data-control protocol of version 2 or above. Together with --clear, the chosen clipboards
+    /// are cleared. Defaults to "regular".
+    #[structopt(long,
+                possible_values = &["regular", "primary", "both"],
+                conflicts_with_all = &["primary", "regular"],
+                value_name = "clipboard")]
+    clipboard: Option<String>,
+
     /// Use the "primary" clipboard
     ///
-    /// Copying to the "primary" clipboard requires the compositor to support the data-control
-    /// protocol of version 2 or above.
+    /// The same as --clipboard primary, kept for compatibility. Prefer --clipboard.
     #[structopt(long, short)]
     primary: bool,
 
     /// Use the regular clipboard
     ///
-    /// Set this flag together with --primary to operate on both clipboards at once. Has no effect
-    /// otherwise (since the regular clipboard is the default clipboard).
+    /// Together with --primary, the same as --clipboard both, kept for compatibility. Has no
+    /// effect otherwise (since the regular clipboard is the default clipboard). Prefer
+    /// --clipboard.
     #[structopt(long, short)]
     regular: bool,
 
+    /// What to do if the "primary" clipboard is not supported
+    ///
+    /// With "regular", wl-copy prints a warning and uses the regular clipboard instead. With
+    /// "exit", wl-copy prints a warning and exits with status 2. By default, an error is reported.
+    /// Has no effect unless the "primary" clipboard is used.
+    #[structopt(long,
+                possible_values = &["regular", "exit"],
+                value_name = "action")]
+    primary_fallback: Option<String>,
+
     /// Trim the trailing newline character before copying
     ///
     /// This flag is only applied for text MIME types.
     #[structopt(long, short = "n", conflicts_with = "clear")]
     trim_newline: bool,
 
+    /// Remove ANSI escape sequences, like colors, before copying
+    ///
+    /// Useful for copying colored terminal output. This flag is only applied for text MIME types.
+    #[structopt(long, conflicts_with = "clear")]
+    strip_ansi: bool,
+
+    /// Don't offer additional text MIME types
+    ///
+    /// By default, when copying text, wl-copy also offers it as text/plain;charset=utf-8,
+    /// text/plain, STRING, UTF8_STRING and TEXT. This flag limits the offers to the inferred or
+    /// specified MIME type. For HTML, it also disables offering the text with the tags stripped.
+    #[structopt(long, conflicts_with = "clear")]
+    omit_additional_text_mime_types: bool,
+
+    /// Offer the additional text MIME types before the specified MIME type
+    ///
+    /// By default, the inferred or specified MIME type is offered first, so that apps taking the
+    /// first suitable offer prefer it. Some apps make a better choice when the plain text MIME
+    /// types come first.
+    #[structopt(long,
+                conflicts_with_all = &["clear", "omit-additional-text-mime-types"])]
+    additional_text_mime_types_first: bool,
+
+    /// Also offer the data under the given MIME type
+    ///
+    /// Can be specified multiple times to offer several additional MIME types, all of which paste
+    /// the same data.
+    #[structopt(long = "additional-type",
+                value_name = "type",
+                number_of_values = 1,
+                conflicts_with = "clear")]
+    additional_types: Vec<String>,
+
+    /// Treat the given MIME type as text
+    ///
+    /// Data of this MIME type gets its trailing newline trimmed with --trim-newline and is offered
+    /// under the additional text MIME types. Can be specified multiple times.
+    #[structopt(long = "text-type",
+                value_name = "type",
+                number_of_values = 1,
+                conflicts_with = "clear")]
+    text_types: Vec<String>,
+
+    /// Create the temporary file in the given directory
+    ///
+    /// By default the system temporary directory is used ($TMPDIR or /tmp).
+    #[structopt(long, value_name = "path", conflicts_with = "clear", parse(from_os_str))]
+    temp_dir: Option<PathBuf>,
+
+    /// Keep the temporary file after exiting instead of removing it
+    ///
+    /// The path of the file is printed to the standard error. Small data is kept in memory and
+    /// has no temporary file.
+    #[structopt(long, conflicts_with = "clear")]
+    keep_temp: bool,
+
+    /// Clear the clipboard after the given number of seconds
+    ///
+    /// The time is counted from the moment the clipboard is set. This is useful for copying
+    /// passwords and other sensitive data.
+    #[structopt(long, value_name = "seconds", conflicts_with = "clear")]
+    expire_after: Option<u64>,
+
+    /// Clear the clipboard if nothing pastes for the given number of seconds
+    ///
+    /// The time is counted from the moment the clipboard is set, and restarts after each paste.
+    /// Useful with --foreground, so that wl-copy doesn't keep running if nothing ever pastes.
+    #[structopt(long, value_name = "seconds", conflicts_with = "clear")]
+    idle_timeout: Option<u64>,
+
+    /// Mark the copied data as sensitive, like a password
+    ///
+    /// Additionally offers a hint MIME type telling clipboard managers not to keep the data in
+    /// their history. See --sensitive-hint-type.
+    #[structopt(long, conflicts_with = "clear")]
+    sensitive: bool,
+
+    /// The hint MIME type to offer with --sensitive
+    ///
+    /// Pasting the hint type gives "secret". Defaults to x-kde-passwordManagerHint, which KDE's
+    /// Klipper and a number of other clipboard managers honor.
+    #[structopt(long, value_name = "type", requires = "sensitive")]
+    sensitive_hint_type: Option<String>,
+
     /// Pick the seat to work with
     ///
     /// By default wl-copy operates on all seats at once.
+    ///
+    /// The seat can also be given by its index, counting from 0 in the order wl-paste
+    /// --list-seats prints the seats. Names take precedence. Seats of version 1, listed as
+    /// <unnamed>, can't be used.
     #[structopt(long, short)]
     seat: Option<String>,
 
     /// Override the inferred MIME type for the content
+    ///
+    /// The special value "text" offers the content under the common plain text MIME types, like
+    /// text/plain;charset=utf-8, the same way as autodetected text.
     #[structopt(name = "mime-type",
                 long = "type",
                 short = "t",
                 conflicts_with = "clear")]
     mime_type: Option<String>,
 
+    /// Copy the contents of the given file
+    ///
+    /// Unless --type is specified, the MIME type is inferred from the file extension.
+    #[structopt(long, conflicts_with_all = &["clear", "text to copy"], parse(from_os_str))]
+    file: Option<PathBuf>,
+
+    /// Copy the data read from the given file descriptor instead of the standard input
+    ///
+    /// The file descriptor must be open for reading, for example one set up with 3<file in the
+    /// shell. It is used just like the standard input.
+    #[structopt(long, value_name = "fd", conflicts_with_all = &["clear", "text to copy", "file"])]
+    fd: Option<RawFd>,
+
+    /// Copy the given files like a file manager does, as a text/uri-list
+    ///
+    /// Can be specified multiple times to copy several files. Pasting into a file manager then
+    /// copies the files themselves.
+    #[structopt(long = "uri",
+                value_name = "path",
+                conflicts_with_all = &["clear", "text to copy", "file", "fd"],
+                parse(from_os_str))]
+    uris: Vec<PathBuf>,
+
+    /// Copy the current contents of the "primary" clipboard into the regular clipboard
+    ///
+    /// The contents are copied under the MIME type they were offered with. Unless --type is
+    /// specified, a plain text MIME type is preferred if available. This requires the compositor
+    /// to support the data-control protocol of version 2 or above.
+    #[structopt(long,
+                conflicts_with_all = &["clear", "text to copy", "file", "fd", "uris", "clipboard",
+                                       "primary"])]
+    from_primary: bool,
+
+    /// Decode the text to copy from base64
+    ///
+    /// Useful for copying binary data, together with --type.
+    #[structopt(long, requires = "text to copy")]
+    base64: bool,
+
     /// Text to copy
     ///
     /// If not specified, wl-copy will use data from the standard input.
     #[structopt(name = "text to copy", conflicts_with = "clear", parse(from_os_str))]
     text: Vec<OsString>,
 
+    /// Print the MIME types that would be offered instead of copying
+    ///
+    /// The data is read and the MIME types are inferred as usual, but the compositor is not
+    /// contacted. Each line lists an offered MIME type along with the data it would serve: MIME
+    /// types with the same data number serve the same data.
+    #[structopt(long, conflicts_with_all = &["clear", "foreground", "print-ready"])]
+    dry_run: bool,
+
+    /// Print a report of the compositor's clipboard support and exit
+    ///
+    /// The report lists the bound data-control protocol version, whether the primary selection is
+    /// supported and the seats with their names. The clipboard is left untouched. Useful to
+    /// include in bug reports.
+    #[structopt(long,
+                conflicts_with_all = &["clear", "dry-run", "text to copy"])]
+    report: bool,
+
+    /// Don't print hints about likely mistakes
+    ///
+    /// For example, wl-copy warns when the only text to copy is the path of an existing file, as
+    /// --file was probably meant instead.
+    #[structopt(long)]
+    no_hints: bool,
+
     /// Enable verbose logging
     #[structopt(long, short, parse(from_occurrences))]
     verbose: usize,
 }
 
+#[derive(Debug, Fail)]
+#[fail(display = "The text to copy is not valid base64")]
+struct InvalidBase64;
+
+#[derive(Debug, Fail)]
+enum InvalidFd {
+    #[fail(display = "File descriptor {} is not open", _0)]
+    NotOpen(RawFd),
+
+    #[fail(display = "File descriptor {} is not open for reading", _0)]
+    NotReadable(RawFd),
+}
+
+/// Replaces the standard input with `fd`, taking ownership of it.
+fn replace_stdin(fd: RawFd) -> Result<(), failure::Error> {
+    let flags = match fcntl(fd, FcntlArg::F_GETFL) {
+        Ok(flags) => OFlag::from_bits_truncate(flags),
+        Err(nix::Error::Sys(Errno::EBADF)) => return Err(InvalidFd::NotOpen(fd).into()),
+        Err(err) => {
+            return Err(err.context(format!("Couldn't get the flags of file descriptor {}", fd))
+                          .into())
+        }
+    };
+
+    if flags & OFlag::O_ACCMODE == OFlag::O_WRONLY {
+        return Err(InvalidFd::NotReadable(fd).into());
+    }
+
+    if fd != STDIN_FILENO {
+        dup2(fd, STDIN_FILENO).context("Couldn't replace the standard input")?;
+        close(fd).context(format!("Couldn't close file descriptor {}", fd))?;
+    }
+
+    Ok(())
+}
+
+/// Decodes standard base64 with optional padding, ignoring whitespace.
+fn decode_base64(input: &[u8]) -> Result<Vec<u8>, InvalidBase64> {
+    let mut data = Vec::with_capacity(input.len() / 4 * 3);
+    let mut symbols = 0;
+    let mut padding = 0;
+    let mut buf = 0u32;
+    let mut bits = 0;
+
+    for &c in input.iter().filter(|c| !c.is_ascii_whitespace()) {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => {
+                padding += 1;
+                continue;
+            }
+            _ => return Err(InvalidBase64),
+        };
+
+        // Padding is only allowed at the end.
+        if padding > 0 {
+            return Err(InvalidBase64);
+        }
+
+        symbols += 1;
+        buf = (buf << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            data.push((buf >> bits) as u8);
+            buf &= (1 << bits) - 1;
+        }
+    }
+
+    // A single leftover symbol doesn't make up a byte, and padding must complete the last group.
+    if symbols % 4 == 1 || padding > 2 || (padding > 0 && (symbols + padding) % 4 != 0) {
+        return Err(InvalidBase64);
+    }
+
+    Ok(data)
+}
+
 impl From<Options> for copy::Options {
     fn from(x: Options) -> Self {
         let mut opts = copy::Options::new();
@@ -94,11 +401,66 @@ impl From<Options> for copy::Options {
                            ClipboardType::Regular
                        })
             .trim_newline(x.trim_newline)
+            .strip_ansi(x.strip_ansi)
+            .omit_additional_text_mime_types(x.omit_additional_text_mime_types)
+            .additional_text_mime_types_first(x.additional_text_mime_types_first)
+            .additional_mime_types(x.additional_types)
+            .text_mime_types(x.text_types)
+            // Streaming in the background would leave the writer blocked after wl-copy returns.
+            .stream_stdin(x.paste_once && x.foreground)
+            .keep_temp_files(x.keep_temp)
             .seat(x.seat.map(Seat::Specific).unwrap_or_default());
+        if let Some(temp_dir) = x.temp_dir {
+            opts.temp_dir(temp_dir);
+        }
+        if let Some(expire_after) = x.expire_after {
+            opts.expire_after(Duration::from_secs(expire_after));
+        }
+        if let Some(idle_timeout) = x.idle_timeout {
+            opts.idle_timeout(Duration::from_secs(idle_timeout));
+        }
         opts
     }
 }
 
+/// Returns `true` if `path` is an existing regular file that can be opened for reading.
+fn looks_like_readable_file(path: &OsString) -> bool {
+    fs::metadata(path).map(|metadata| metadata.is_file()).unwrap_or(false)
+    && fs::File::open(path).is_ok()
+}
+
+/// Prints the clipboard capabilities of the compositor.
+fn print_report() -> Result<(), failure::Error> {
+    // Use a single connection, so that everything describes the same compositor state.
+    let mut connection = Connection::new()?;
+    println!("Data-control protocol: zwlr_data_control_manager_v1 version {}",
+             connection.data_control_version());
+
+    let primary = match is_primary_selection_supported_with_connection(&mut connection) {
+        Ok(true) => "supported",
+        Ok(false) => "not supported",
+        // A seat is needed to tell.
+        Err(PrimarySelectionCheckError::NoSeats) => "unknown (no seats)",
+        Err(err) => return Err(err.into()),
+    };
+    println!("Primary selection: {}", primary);
+
+    let seat_names = get_seat_names_with_connection(&mut connection)?;
+    println!("Seats: {}", seat_names.len());
+    for name in seat_names {
+        println!("  {}", name.as_deref().unwrap_or("<unnamed>"));
+    }
+
+    Ok(())
+}
+
+fn print_pid(pid: Pid) -> Result<(), failure::Error> {
+    let mut stdout = stdout();
+    writeln!(stdout, "{}", pid).context("Couldn't write the PID to stdout")?;
+    stdout.flush().context("Couldn't flush stdout")?;
+    Ok(())
+}
+
 fn main() -> Result<(), ExitFailure> {
     // Parse command-line options.
     let mut options = Options::from_args();
@@ -107,9 +469,48 @@ fn main() -> Result<(), ExitFailure> {
                     .init()
                     .unwrap();
 
+    if options.report {
+        print_report()?;
+        return Ok(());
+    }
+
+    // The rest of the code works with the --primary and --regular flags.
+    match options.clipboard.take().as_deref() {
+        Some("primary") => options.primary = true,
+        Some("both") => {
+            options.primary = true;
+            options.regular = true;
+        }
+        _ => {}
+    }
+
+    // Check for the primary selection support up front if asked to handle it missing.
+    if let (true, Some(fallback)) = (options.primary, &options.primary_fallback) {
+        let supported = match is_primary_selection_supported() {
+            Ok(supported) => supported,
+            // Let the copy below report the missing protocol.
+            Err(PrimarySelectionCheckError::MissingProtocol { .. }) => true,
+            Err(err) => return Err(err.into()),
+        };
+
+        if !supported {
+            if fallback == "exit" {
+                warn!("The compositor does not support primary selection");
+                process::exit(2);
+            }
+
+            warn!("The compositor does not support primary selection, using the regular clipboard");
+            options.primary = false;
+        }
+    }
+
     if options.clear {
         let clipboard = if options.primary {
-            ClipboardType::Primary
+            if options.regular {
+                ClipboardType::Both
+            } else {
+                ClipboardType::Primary
+            }
         } else {
             ClipboardType::Regular
         };
@@ -118,6 +519,17 @@ fn main() -> Result<(), ExitFailure> {
         return Ok(());
     }
 
+    // Copying a file name instead of the file contents is a common mistake.
+    if !options.no_hints && options.mime_type.is_none() && !options.base64 {
+        if let [ref arg] = options.text[..] {
+            if looks_like_readable_file(arg) {
+                warn!("Copying the path {:?} as text; use --file or `wl-copy < {0:?}` to copy the \
+                       file contents instead (pass --no-hints to silence this)",
+                      arg);
+            }
+        }
+    }
+
     // Is there a way to do this without checking twice?
     let source_data = if options.text.is_empty() {
         None
@@ -134,30 +546,209 @@ fn main() -> Result<(), ExitFailure> {
         Some(data)
     };
 
-    let source = if let Some(source_data) = source_data {
-        Source::Bytes(source_data.into_vec().into())
+    let mut mime_type = options.mime_type.take().map(|x| {
+                                                     if x == "text" {
+                                                         MimeType::Text
+                                                     } else {
+                                                         MimeType::Specific(x)
+                                                     }
+                                                 });
+
+    let source = if options.from_primary {
+        let requested_mime_type = mime_type.take();
+        let paste_mime_type = match requested_mime_type {
+            Some(MimeType::Specific(ref x)) => paste::MimeType::Specific(x),
+            Some(MimeType::Text) => paste::MimeType::Text,
+            _ => paste::MimeType::Any,
+        };
+        let seat = options.seat.as_deref().map(paste::Seat::Specific).unwrap_or_default();
+        let (mut pipe, primary_mime_type) =
+            paste::get_contents(paste::ClipboardType::Primary, seat, paste_mime_type)
+            .context("Couldn't get the contents of the primary clipboard")?;
+
+        let mut data = Vec::new();
+        pipe.read_to_end(&mut data)
+            .context("Couldn't read the contents of the primary clipboard")?;
+
+        mime_type = Some(MimeType::Specific(primary_mime_type));
+        Source::Bytes(data.into())
+    } else if !options.uris.is_empty() {
+        let mut paths = Vec::new();
+        for path in &options.uris {
+            let path = fs::canonicalize(path).context(format!("Couldn't find {}",
+                                                              path.to_string_lossy()))?;
+            paths.push(path);
+        }
+
+        if mime_type.is_none() {
+            mime_type = Some(MimeType::Specific("text/uri-list".to_string()));
+        }
+
+        Source::Bytes(uri_list(&paths).into_bytes().into())
+    } else if let Some(path) = options.file.take() {
+        // Infer the MIME type from the file extension unless it was specified explicitly.
+        if mime_type.is_none() {
+            mime_type =
+                mime_from_extension(&path)
+                .or_else(|| mime_guess::from_path(&path).first().map(|x| x.to_string()))
+                .map(MimeType::Specific);
+        }
+
+        let data =
+            fs::read(&path).context(format!("Couldn't read {}", path.to_string_lossy()))?;
+        Source::Bytes(data.into())
+    } else if let Some(source_data) = source_data {
+        let mut data = source_data.into_vec();
+        if options.base64 {
+            data = decode_base64(&data)?;
+        }
+        Source::Bytes(data.into())
     } else {
+        if let Some(fd) = options.fd {
+            replace_stdin(fd)?;
+        }
         Source::StdIn
     };
 
-    let mime_type = if let Some(mime_type) = options.mime_type.take() {
-        MimeType::Specific(mime_type)
+    let mime_type = mime_type.unwrap_or(MimeType::Autodetect);
+
+    let mut sources = vec![MimeSource { source, mime_type }];
+    if options.sensitive {
+        let hint_type = options.sensitive_hint_type
+                               .take()
+                               .unwrap_or_else(|| "x-kde-passwordManagerHint".to_string());
+        sources.push(MimeSource { source: Source::Bytes(b"secret"[..].into()),
+                                  mime_type: MimeType::Specific(hint_type) });
+    }
+
+    if options.dry_run {
+        for offer in copy::dry_run(&copy::Options::from(options), sources)? {
+            match offer.size {
+                Some(size) => println!("{}\tdata {}, {} bytes", offer.mime_type, offer.data, size),
+                None => println!("{}\tdata {}, streamed from stdin", offer.mime_type, offer.data),
+            }
+        }
+
+        return Ok(());
+    }
+
+    let foreground = options.foreground;
+    let print_ready = options.print_ready;
+
+    // In the foreground, stop cleanly on SIGTERM (e.g. from a service manager) rather than dying
+    // with the temporary files left behind.
+    let sigterm = if foreground {
+        let mut mask = SigSet::empty();
+        mask.add(Signal::SIGTERM);
+        mask.thread_block().context("Couldn't block SIGTERM")?;
+        let sigterm = SignalFd::with_flags(&mask, SfdFlags::SFD_CLOEXEC)
+            .context("Couldn't create a signalfd for SIGTERM")?;
+        Some(sigterm)
     } else {
-        MimeType::Autodetect
+        None
     };
 
-    let foreground = options.foreground;
-    let prepared_copy = copy::Options::from(options).prepare_copy(source, mime_type)?;
+    let keep_temp = options.keep_temp;
+    let prepared_copy = copy::Options::from(options).prepare_copy_multi(sources)?;
+
+    if keep_temp {
+        for path in prepared_copy.temp_files() {
+            eprintln!("Keeping the temporary file {}", path.to_string_lossy());
+        }
+    }
+
+    if let Some(sigterm) = sigterm {
+        if print_ready {
+            print_pid(Pid::this())?;
+        }
 
-    if foreground {
-        prepared_copy.serve()?;
+        let result = prepared_copy.serve_until_cancelled(sigterm.as_raw_fd());
+        log::logger().flush();
+        result?;
     } else {
         // We don't spawn any threads, so doing things after forking is safe.
         // TODO: is there any way to verify that we don't spawn any threads?
-        if let ForkResult::Child = fork().unwrap() {
-            drop(prepared_copy.serve());
+        match fork() {
+            Ok(ForkResult::Child) => drop(prepared_copy.serve()),
+            Ok(ForkResult::Parent { child }) => {
+                // The temp files are now owned by the child, don't let the parent remove them.
+                mem::forget(prepared_copy);
+
+                if print_ready {
+                    print_pid(child)?;
+                }
+            }
+            Err(err) => {
+                // Still copy (for example when out of PIDs), just without returning right away.
+                warn!("Couldn't fork, serving in the foreground instead: {}", err);
+
+                if print_ready {
+                    print_pid(Pid::this())?;
+                }
+
+                prepared_copy.serve()?;
+            }
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_base64_padding() {
+        assert_eq!(decode_base64(b"").unwrap(), b"");
+        assert_eq!(decode_base64(b"QQ==").unwrap(), b"A");
+        assert_eq!(decode_base64(b"QUI=").unwrap(), b"AB");
+        assert_eq!(decode_base64(b"QUJD").unwrap(), b"ABC");
+        assert_eq!(decode_base64(b"aGVsbG8gd29ybGQ=").unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn decode_base64_missing_padding() {
+        assert_eq!(decode_base64(b"QQ").unwrap(), b"A");
+        assert_eq!(decode_base64(b"QUI").unwrap(), b"AB");
+        assert_eq!(decode_base64(b"aGVsbG8gd29ybGQ").unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn decode_base64_whitespace() {
+        assert_eq!(decode_base64(b"  QU JD\n").unwrap(), b"ABC");
+        assert_eq!(decode_base64(b"aGVsbG8g\nd29ybGQ=\n").unwrap(), b"hello world");
+        assert_eq!(decode_base64(b"aGVsbG8g\r\nd29y\tbGQ=").unwrap(), b"hello world");
+        assert_eq!(decode_base64(b"QQ=\n=\n").unwrap(), b"A");
+    }
+
+    #[test]
+    fn decode_base64_binary() {
+        assert_eq!(decode_base64(b"AP8=").unwrap(), [0x00, 0xff]);
+        assert_eq!(decode_base64(b"+/+/").unwrap(), [0xfb, 0xff, 0xbf]);
+    }
+
+    #[test]
+    fn decode_base64_invalid_characters() {
+        assert!(decode_base64(b"QUJD!").is_err());
+        assert!(decode_base64(b"QU-D").is_err());
+        assert!(decode_base64(b"QU_D").is_err());
+        assert!(decode_base64("QUJDé".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn decode_base64_invalid_padding() {
+        assert!(decode_base64(b"QQ=").is_err());
+        assert!(decode_base64(b"QQ===").is_err());
+        assert!(decode_base64(b"Q===").is_err());
+        assert!(decode_base64(b"QQ==QQ==").is_err());
+        assert!(decode_base64(b"=QUJD").is_err());
+    }
+
+    #[test]
+    fn decode_base64_dangling_group() {
+        assert!(decode_base64(b"Q").is_err());
+        assert!(decode_base64(b"QUJDR").is_err());
+        assert!(decode_base64(b"QUJDR\n").is_err());
+    }
+}
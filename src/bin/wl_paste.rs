@@ -1,17 +1,23 @@
 #![deny(unsafe_code)]
 
 use std::{
-    fs::read_link,
-    io::{stdout, Read, Write},
+    ffi::OsString,
+    fs::{read_link, remove_file, File, OpenOptions},
+    io::{self, stdout, Read, Write},
+    path::{Path, PathBuf},
+    process::Command,
 };
 
 use exitfailure::ExitFailure;
 use failure::ResultExt;
 use libc::STDOUT_FILENO;
-use log::info;
+use log::{error, info};
 use mime_guess::Mime;
 use structopt::{clap::AppSettings, StructOpt};
-use wl_clipboard_rs::{paste::*, utils::is_text};
+use wl_clipboard_rs::{
+    paste::*,
+    utils::{get_seat_names, is_text, trim_trailing_newline, CountingReader},
+};
 
 #[derive(StructOpt)]
 #[structopt(name = "wl-paste",
@@ -23,6 +29,40 @@ struct Options {
     #[structopt(long, short)]
     list_types: bool,
 
+    /// Print the MIME types as a JSON array of strings
+    #[structopt(long, requires = "list-types")]
+    json: bool,
+
+    /// Separate the listed MIME types with NUL characters instead of newlines
+    ///
+    /// Like find -print0, for use with xargs -0 and similar tools.
+    #[structopt(long, short = "0", requires = "list-types", conflicts_with = "json")]
+    null: bool,
+
+    /// List the seat names for use with --seat instead of pasting
+    ///
+    /// Seats that don't advertise a name are listed as <unnamed>.
+    #[structopt(long,
+                conflicts_with_all = &["list-types",
+                                       "byte-count",
+                                       "primary",
+                                       "no-newline",
+                                       "trim-newline",
+                                       "seat",
+                                       "mime-type",
+                                       "watch"])]
+    list_seats: bool,
+
+    /// Print the size of the clipboard contents in bytes instead of pasting
+    ///
+    /// The contents are read in full and discarded. The newline handling options don't apply.
+    #[structopt(long,
+                conflicts_with_all = &["list-types",
+                                       "no-newline",
+                                       "trim-newline",
+                                       "watch"])]
+    byte_count: bool,
+
     /// Use the "primary" clipboard
     ///
     /// Pasting to the "primary" clipboard requires the compositor to support the data-control
@@ -36,11 +76,21 @@ struct Options {
     #[structopt(long, short, conflicts_with = "list-types")]
     no_newline: bool,
 
+    /// Trim the trailing newline character of the clipboard contents
+    ///
+    /// This flag is only applied for text MIME types, and implies --no-newline.
+    #[structopt(long, conflicts_with = "list-types")]
+    trim_newline: bool,
+
     /// Pick the seat to work with
     ///
     /// By default the seat used is unspecified (it depends on the order returned by the
     /// compositor). This is perfectly fine when only a single seat is present, so for most
     /// configurations.
+    ///
+    /// The seat can also be given by its index, counting from 0 in the order wl-paste
+    /// --list-seats prints the seats. Names take precedence. Seats of version 1, listed as
+    /// <unnamed>, can't be used.
     #[structopt(long, short)]
     seat: Option<String>,
 
@@ -48,25 +98,148 @@ struct Options {
     ///
     /// As a special case, specifying "text" will look for a number of plain text types,
     /// prioritizing ones that are known to give UTF-8 text.
+    ///
+    /// The MIME type can contain * to match any sequence of characters, for example image/*. The
+    /// first matching offered MIME type is then requested.
     #[structopt(name = "mime-type",
                 long = "type",
                 short = "t",
                 conflicts_with = "list-types")]
     mime_type: Option<String>,
 
+    /// Write the clipboard contents into the given file instead of the standard output
+    ///
+    /// The file is opened before the clipboard is read, but only replaced once the contents are
+    /// read in full, so a failed paste leaves it as it was. Unless --type is specified, the MIME
+    /// type is inferred from the file extension.
+    #[structopt(long,
+                short,
+                value_name = "path",
+                conflicts_with_all = &["list-types", "list-seats", "byte-count", "watch"],
+                parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Run a command each time the clipboard contents change
+    ///
+    /// The command receives the new clipboard contents on its standard input. All arguments
+    /// following this option are passed to the command. Clearing the clipboard does not run the
+    /// command.
+    #[structopt(long,
+                short,
+                conflicts_with = "list-types",
+                min_values = 1,
+                allow_hyphen_values = true,
+                value_name = "command",
+                parse(from_os_str))]
+    watch: Option<Vec<OsString>>,
+
     /// Enable verbose logging
     #[structopt(long, short, parse(from_occurrences))]
     verbose: usize,
 }
 
-fn infer_mime_type() -> Option<Mime> {
-    if let Ok(stdout_path) = read_link(&format!("/dev/fd/{}", STDOUT_FILENO)) {
+/// The file given with --output.
+///
+/// The file is only truncated when writing, and a file created by opening is removed again if
+/// nothing was written, so that a failed paste leaves things as they were.
+struct Output {
+    file: File,
+    path: PathBuf,
+    created: bool,
+    written: bool,
+}
+
+impl Output {
+    fn open(path: &Path) -> Result<Self, failure::Error> {
+        let result = OpenOptions::new().write(true).create_new(true).open(path);
+        let (file, created) = match result {
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                (OpenOptions::new().write(true).open(path), false)
+            }
+            result => (result, true),
+        };
+        let file = file.with_context(|_| format!("Couldn't open {}", path.to_string_lossy()))?;
+
+        Ok(Self { file,
+                  path: path.to_path_buf(),
+                  created,
+                  written: false })
+    }
+
+    /// Replaces the contents of the file with `contents`.
+    fn write(mut self, contents: &[u8]) -> Result<(), failure::Error> {
+        self.written = true;
+
+        let path = self.path.to_string_lossy().into_owned();
+        let context = || format!("Couldn't write contents to {}", path);
+
+        // Devices and pipes, like /dev/stdout, can't be truncated.
+        if self.file.metadata().with_context(|_| context())?.is_file() {
+            self.file.set_len(0).with_context(|_| context())?;
+        }
+        self.file.write_all(contents).with_context(|_| context())?;
+
+        Ok(())
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        if self.created && !self.written {
+            let _ = remove_file(&self.path);
+        }
+    }
+}
+
+fn infer_mime_type(output: Option<&Path>) -> Option<Mime> {
+    if let Some(path) = output {
+        mime_guess::from_path(path).first()
+    } else if let Ok(stdout_path) = read_link(&format!("/dev/fd/{}", STDOUT_FILENO)) {
         mime_guess::from_path(stdout_path).first()
     } else {
         None
     }
 }
 
+/// Parses the MIME type given with --type.
+fn parse_mime_type(mime_type: &str) -> MimeType<'_> {
+    if mime_type == "text" {
+        MimeType::Text
+    } else if mime_type.contains('*') {
+        MimeType::Pattern(mime_type)
+    } else {
+        MimeType::Specific(mime_type)
+    }
+}
+
+/// Formats the strings as a JSON array.
+fn json_array<'a>(strings: impl IntoIterator<Item = &'a String>) -> String {
+    let mut json = String::from("[");
+
+    for (i, string) in strings.into_iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+
+        json.push('"');
+        for c in string.chars() {
+            match c {
+                '"' => json.push_str("\\\""),
+                '\\' => json.push_str("\\\\"),
+                '\n' => json.push_str("\\n"),
+                '\r' => json.push_str("\\r"),
+                '\t' => json.push_str("\\t"),
+                c if c < ' ' => json.push_str(&format!("\\u{:04x}", c as u32)),
+                c => json.push(c),
+            }
+        }
+        json.push('"');
+    }
+
+    json.push(']');
+    json
+}
+
 fn main() -> Result<(), ExitFailure> {
     // Parse command-line options.
     let options = Options::from_args();
@@ -84,30 +257,73 @@ fn main() -> Result<(), ExitFailure> {
                     .init()
                     .unwrap();
 
+    // If listing seats is requested, do just that.
+    if options.list_seats {
+        for name in get_seat_names()? {
+            println!("{}", name.as_deref().unwrap_or("<unnamed>"));
+        }
+
+        return Ok(());
+    }
+
     // If listing types is requested, do just that.
     if options.list_types {
         let mime_types = get_mime_types(primary, seat)?;
 
-        for mime_type in mime_types.iter() {
-            println!("{}", mime_type);
+        if options.json {
+            println!("{}", json_array(&mime_types));
+        } else if options.null {
+            for mime_type in mime_types.iter() {
+                print!("{}\0", mime_type);
+            }
+        } else {
+            for mime_type in mime_types.iter() {
+                println!("{}", mime_type);
+            }
         }
 
         return Ok(());
     }
 
+    // If watching is requested, run the command on every clipboard change.
+    if let Some(ref command) = options.watch {
+        let mime_type = options.mime_type
+                               .as_deref()
+                               .map(parse_mime_type)
+                               .unwrap_or(MimeType::Any);
+
+        watch(primary, seat, mime_type, |read, mime_type| {
+            info!("Clipboard contents changed, MIME type: {}", mime_type);
+
+            // Waiting for the command to exit makes sure we don't leave zombie processes behind.
+            let status = Command::new(&command[0]).args(&command[1..]).stdin(read).status();
+            if let Err(err) = status {
+                error!("Couldn't run the command: {}", err);
+            }
+        })?;
+
+        return Ok(());
+    }
+
     // Otherwise, get the clipboard contents.
 
+    // Open the output file up front, so that an unwritable path fails before touching the
+    // clipboard.
+    let output = match options.output {
+        Some(ref path) => Some(Output::open(path)?),
+        None => None,
+    };
+
     // No MIME type specified—try inferring one from the output file extension (if any).
-    let inferred = if options.mime_type.is_none() {
-        infer_mime_type()
+    let inferred = if options.mime_type.is_none() && !options.byte_count {
+        infer_mime_type(options.output.as_deref())
     } else {
         None
     };
 
     // Do some smart MIME type selection.
     let mime_type = match options.mime_type {
-        Some(ref mime_type) if mime_type == "text" => MimeType::Text,
-        Some(ref mime_type) => MimeType::Specific(mime_type),
+        Some(ref mime_type) => parse_mime_type(mime_type),
         None => {
             let inferred: Option<&str> = inferred.as_ref().map(Mime::as_ref);
             info!("Inferred MIME type: {:?}", inferred);
@@ -123,20 +339,76 @@ fn main() -> Result<(), ExitFailure> {
 
     let (mut read, mime_type) = get_contents(primary, seat, mime_type)?;
 
+    // If the size is requested, count the bytes without keeping them.
+    if options.byte_count {
+        let mut read = CountingReader::new(read);
+        io::copy(&mut read, &mut io::sink()).context("Couldn't read clipboard contents")?;
+        println!("{}", read.bytes_read());
+        return Ok(());
+    }
+
     // Read the contents.
     let mut contents = vec![];
     read.read_to_end(&mut contents)
         .context("Couldn't read clipboard contents")?;
 
-    // Append a newline if needed.
-    let last_character_is_newline = contents.last().map(|&c| c == b'\n').unwrap_or(false);
-    if !options.no_newline && is_text(&mime_type) && !last_character_is_newline {
-        contents.push(b'\n');
+    // Trim the trailing newline if requested, otherwise append one if needed.
+    if options.trim_newline && is_text(&mime_type) {
+        trim_trailing_newline(&mut contents);
+    } else {
+        let last_character_is_newline = contents.last().map(|&c| c == b'\n').unwrap_or(false);
+        if !options.no_newline && is_text(&mime_type) && !last_character_is_newline {
+            contents.push(b'\n');
+        }
     }
 
-    // Write everything to stdout.
-    stdout().write_all(&contents)
-            .context("Couldn't write contents to stdout")?;
+    // Write everything to the output file or stdout.
+    if let Some(output) = output {
+        output.write(&contents)?;
+    } else {
+        stdout().write_all(&contents)
+                .context("Couldn't write contents to stdout")?;
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mime_type_test() {
+        assert_eq!(parse_mime_type("text"), MimeType::Text);
+        assert_eq!(parse_mime_type("image/*"), MimeType::Pattern("image/*"));
+        assert_eq!(parse_mime_type("*"), MimeType::Pattern("*"));
+        assert_eq!(parse_mime_type("text/html"), MimeType::Specific("text/html"));
+        assert_eq!(parse_mime_type("text/plain"), MimeType::Specific("text/plain"));
+    }
+
+    fn json(strings: &[&str]) -> String {
+        let strings: Vec<String> = strings.iter().map(|s| s.to_string()).collect();
+        json_array(&strings)
+    }
+
+    #[test]
+    fn json_array_test() {
+        assert_eq!(json(&[]), "[]");
+        assert_eq!(json(&["text/plain"]), r#"["text/plain"]"#);
+        assert_eq!(json(&["text/plain", "image/png"]), r#"["text/plain","image/png"]"#);
+    }
+
+    #[test]
+    fn json_array_escapes() {
+        assert_eq!(json(&["a\"b"]), r#"["a\"b"]"#);
+        assert_eq!(json(&["a\\b"]), r#"["a\\b"]"#);
+        assert_eq!(json(&["a\nb\rc\td"]), r#"["a\nb\rc\td"]"#);
+        assert_eq!(json(&["\u{0}\u{1}\u{1b}\u{1f}"]), r#"["\u0000\u0001\u001b\u001f"]"#);
+        assert_eq!(json(&[" ~\u{7f}"]), "[\" ~\u{7f}\"]");
+    }
+
+    #[test]
+    fn json_array_non_ascii() {
+        assert_eq!(json(&["héllo", "日本語", "🦀"]), r#"["héllo","日本語","🦀"]"#);
+    }
+}
@@ -1,18 +1,37 @@
-use std::{cell::RefCell, ffi::OsString, io, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    ffi::OsString,
+    io,
+    rc::Rc,
+};
 
 use failure::Fail;
+use log::info;
 use wayland_client::{
-    global_filter, protocol::wl_seat::WlSeat, ConnectError, Display, EventQueue, GlobalManager,
-    Interface, NewProxy,
+    protocol::{wl_registry::WlRegistry, wl_seat::WlSeat},
+    ConnectError, Display, EventQueue, GlobalEvent, GlobalManager, Interface, NewProxy,
 };
 use wayland_protocols::wlr::unstable::data_control::v1::client::zwlr_data_control_manager_v1::ZwlrDataControlManagerV1;
 
-use crate::{handlers::WlSeatHandler, seat_data::SeatData};
+use crate::{
+    handlers::{DataDeviceHandler, WlSeatHandler},
+    seat_data::SeatData,
+};
+
+/// The currently existing seats.
+pub type Seats = Rc<RefCell<Vec<WlSeat>>>;
+
+/// The global ids of the currently existing seats, in the order they were advertised.
+///
+/// Unlike `Seats`, this includes the seats of version 1, which aren't bound.
+pub type SeatGlobals = Rc<RefCell<Vec<u32>>>;
 
 pub struct CommonData {
+    pub display: Display,
     pub queue: EventQueue,
     pub clipboard_manager: ZwlrDataControlManagerV1,
-    pub seats: Rc<RefCell<Vec<WlSeat>>>,
+    pub seats: Seats,
+    pub seat_globals: SeatGlobals,
 }
 
 #[derive(Fail, Debug)]
@@ -26,9 +45,146 @@ pub enum Error {
     #[fail(display = "A required Wayland protocol ({} version {}) is not supported by the compositor",
            name, version)]
     MissingProtocol { name: &'static str, version: u32 },
+
+    #[fail(display = "The {} global was removed before it could be bound", name)]
+    GlobalBindFailed { name: &'static str },
+}
+
+/// Errors that can occur when creating the data devices.
+pub enum DevicesError {
+    NoSeats,
+    WaylandCommunication(io::Error),
+    PrimarySelectionUnsupported,
+}
+
+/// Creates the data devices of all seats, replacing the previous ones.
+///
+/// The devices report the seats' offers as well, so those are up to date afterwards too.
+pub fn create_devices(common: &mut CommonData, primary: bool) -> Result<(), DevicesError> {
+    let CommonData { queue,
+                     clipboard_manager,
+                     seats,
+                     .. } = common;
+
+    // Check if there are no seats.
+    if seats.borrow_mut().is_empty() {
+        return Err(DevicesError::NoSeats);
+    }
+
+    let supports_primary = Rc::new(Cell::new(false));
+
+    // Go through the seats and get their data devices.
+    for seat in &*seats.borrow_mut() {
+        let handler = DataDeviceHandler::new(seat.clone(), primary, supports_primary.clone());
+        let device =
+            clipboard_manager.get_data_device(seat, |device| device.implement(handler, ()))
+                             .unwrap();
+
+        // Replacing the device destroys the one from the previous operation on this connection.
+        let seat_data = seat.as_ref().user_data::<RefCell<SeatData>>().unwrap();
+        seat_data.borrow_mut().set_device(Some(device));
+    }
+
+    // Retrieve all seat names and offers.
+    //
+    // The seats were bound during the previous roundtrip, so their names, which the compositor
+    // sends in response to binding or at the latest along with the devices, arrive during this
+    // one. Matching the requested seat by name only happens after it.
+    queue.sync_roundtrip()
+         .map_err(DevicesError::WaylandCommunication)?;
+
+    // Check if the compositor supports primary selection.
+    if primary && !supports_primary.get() {
+        return Err(DevicesError::PrimarySelectionUnsupported);
+    }
+
+    Ok(())
+}
+
+/// Errors that can occur when finding a seat.
+pub enum SeatError {
+    NotFound,
+    Version1,
+}
+
+/// Returns a global callback that binds all seats into `seats` and forgets the removed ones.
+///
+/// The ids of all seat globals, including the unbound ones of version 1, go into `seat_globals`.
+pub fn track_seats(seats: Seats, seat_globals: SeatGlobals) -> impl FnMut(GlobalEvent, WlRegistry) {
+    move |event, registry| match event {
+        GlobalEvent::New { id,
+                           interface,
+                           version, } => {
+            if interface == WlSeat::NAME {
+                seat_globals.borrow_mut().push(id);
+            }
+
+            if interface == WlSeat::NAME && version >= 2 {
+                let seat_data = RefCell::new(SeatData::new(id));
+                let seat = registry.bind::<WlSeat, _>(version, id, |seat| {
+                                       seat.implement(WlSeatHandler, seat_data)
+                                   })
+                                   .expect("wl_registry died unexpectedly");
+                seats.borrow_mut().push(seat);
+            }
+        }
+        GlobalEvent::Removed { id, interface } => {
+            if interface == WlSeat::NAME {
+                seat_globals.borrow_mut().retain(|&x| x != id);
+
+                // Forget about the seat and its data device so that they aren't used anymore.
+                seats.borrow_mut().retain(|seat| {
+                                      let data = seat.as_ref()
+                                                     .user_data::<RefCell<SeatData>>()
+                                                     .unwrap();
+                                      if data.borrow().global_id != id {
+                                          return true;
+                                      }
+
+                                      let mut data = data.borrow_mut();
+                                      data.set_offer(None);
+                                      data.set_device(None);
+                                      false
+                                  });
+            }
+        }
+    }
+}
+
+/// Finds the seat with the given name.
+///
+/// If no seat has this name and it's a number, falls back to picking the seat with this index in
+/// `seat_globals`, which is the order `utils::get_seat_names()` lists the seats in. Seats of
+/// version 1 are counted, but can't be picked since they aren't bound.
+pub fn find_seat<'a>(seats: &'a [WlSeat],
+                     seat_globals: &[u32],
+                     name: &str)
+                     -> Result<&'a WlSeat, SeatError> {
+    let seat_data = |seat: &'a WlSeat| seat.as_ref().user_data::<RefCell<SeatData>>().unwrap();
+
+    if let Some(seat) = seats.iter()
+                             .find(|seat| seat_data(seat).borrow().name.as_deref() == Some(name))
+    {
+        return Ok(seat);
+    }
+
+    let id = name.parse::<usize>()
+                 .ok()
+                 .and_then(|index| seat_globals.get(index))
+                 .ok_or(SeatError::NotFound)?;
+    seats.iter()
+         .find(|seat| seat_data(seat).borrow().global_id == *id)
+         .ok_or(SeatError::Version1)
 }
 
 pub fn initialize(primary: bool, socket_name: Option<OsString>) -> Result<CommonData, Error> {
+    // Prefer data-control version 2, but fall back to version 1 if we don't need the primary
+    // selection support.
+    connect(if primary { 2 } else { 1 }, socket_name)
+}
+
+/// Connects to the compositor and binds the data-control manager of at least `min_version`.
+pub fn connect(min_version: u32, socket_name: Option<OsString>) -> Result<CommonData, Error> {
     // Connect to the Wayland compositor.
     let (display, mut queue) = match socket_name {
                                    Some(name) => Display::connect_to_name(name),
@@ -36,35 +192,104 @@ pub fn initialize(primary: bool, socket_name: Option<OsString>) -> Result<Common
                                }.map_err(Error::WaylandConnection)?;
 
     let seats = Rc::new(RefCell::new(Vec::<WlSeat>::new()));
+    let seat_globals = Rc::new(RefCell::new(Vec::new()));
+
+    // Remember whether the data-control manager was ever advertised, to tell it missing apart from
+    // it having been removed before we got to bind it.
+    let manager_advertised = Rc::new(Cell::new(false));
+    // GlobalManager binds the first advertised global of an interface, so keep the registry for
+    // binding the best one ourselves.
+    let registry = Rc::new(RefCell::new(None::<WlRegistry>));
+    let mut track_seats = track_seats(seats.clone(), seat_globals.clone());
+    let global_manager = {
+        let manager_advertised = manager_advertised.clone();
+        let registry = registry.clone();
+        GlobalManager::new_with_cb(&display, move |event, wl_registry| {
+            if let GlobalEvent::New { ref interface, .. } = event {
+                if interface == ZwlrDataControlManagerV1::NAME {
+                    manager_advertised.set(true);
+                }
+            }
 
-    let seats_2 = seats.clone();
-    let global_manager =
-        GlobalManager::new_with_cb(&display,
-                                   global_filter!([WlSeat, 2, move |seat: NewProxy<WlSeat>| {
-                                                      let seat_data =
-                                                          RefCell::new(SeatData::default());
-                                                      let seat =
-                                                          seat.implement(WlSeatHandler, seat_data);
-                                                      seats_2.borrow_mut().push(seat.clone());
-                                                      seat
-                                                  }]));
+            registry.borrow_mut().get_or_insert_with(|| wl_registry.clone());
+            track_seats(event, wl_registry)
+        })
+    };
 
     // Retrieve the global interfaces.
     queue.sync_roundtrip()
          .map_err(Error::WaylandCommunication)?;
 
     // Check that we have our interfaces.
-    let data_control_version = if primary { 2 } else { 1 };
+    let managers = global_manager.list()
+                                 .into_iter()
+                                 .filter(|(_, interface, _)| {
+                                     interface == ZwlrDataControlManagerV1::NAME
+                                 })
+                                 .map(|(id, _, version)| (id, version))
+                                 .collect::<Vec<_>>();
+    let (id, version) = match pick_manager(&managers) {
+        Some(x) => x,
+        None if manager_advertised.get() => {
+            return Err(Error::GlobalBindFailed { name: ZwlrDataControlManagerV1::NAME });
+        }
+        None => {
+            return Err(Error::MissingProtocol { name: ZwlrDataControlManagerV1::NAME,
+                                                version: min_version });
+        }
+    };
+
+    if version < min_version {
+        return Err(Error::MissingProtocol { name: ZwlrDataControlManagerV1::NAME,
+                                            version: min_version });
+    }
+
+    if managers.len() > 1 {
+        info!("{} is advertised {} times, picking the one of version {}",
+              ZwlrDataControlManagerV1::NAME,
+              managers.len(),
+              version);
+    }
 
     let impl_manager = |manager: NewProxy<_>| manager.implement_dummy();
     let clipboard_manager =
-        global_manager.instantiate_exact::<ZwlrDataControlManagerV1, _>(data_control_version,
-                                                                        impl_manager)
-                      .map_err(|_| Error::MissingProtocol { name:
-                                                                ZwlrDataControlManagerV1::NAME,
-                                                            version: data_control_version })?;
+        registry.borrow()
+                .as_ref()
+                .unwrap()
+                .bind::<ZwlrDataControlManagerV1, _>(version.min(2), id, impl_manager)
+                .expect("wl_registry died unexpectedly");
+    info!("Bound {} version {}",
+          ZwlrDataControlManagerV1::NAME,
+          clipboard_manager.as_ref().version());
 
-    Ok(CommonData { queue,
+    Ok(CommonData { display,
+                    queue,
                     clipboard_manager,
-                    seats })
+                    seats,
+                    seat_globals })
+}
+
+/// Picks the data-control manager global to bind out of the advertised `(id, version)` pairs.
+///
+/// The highest version wins, so that the primary selection is supported whenever possible. Among
+/// globals of the same version, the first advertised one is picked.
+pub(crate) fn pick_manager(managers: &[(u32, u32)]) -> Option<(u32, u32)> {
+    managers.iter()
+            .copied()
+            .fold(None, |best, (id, version)| match best {
+                Some((_, best_version)) if best_version >= version => best,
+                _ => Some((id, version)),
+            })
+}
+
+/// Checks that the bound data-control manager is recent enough for the requested operation.
+///
+/// Used for connections made once and reused for multiple operations.
+pub fn check_version(common: &CommonData, primary: bool) -> Result<(), Error> {
+    if primary && common.clipboard_manager.as_ref().version() < 2 {
+        return Err(Error::MissingProtocol { name: ZwlrDataControlManagerV1::NAME,
+                                            version: 2 });
+    }
+
+    Ok(())
 }
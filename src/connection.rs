@@ -0,0 +1,87 @@
+//! Reusing one Wayland connection for multiple operations.
+
+use std::{ffi::OsString, io};
+
+use failure::Fail;
+use wayland_client::ConnectError;
+
+use crate::common::{self, connect, CommonData};
+
+/// A connection to the Wayland compositor that can be reused for multiple operations.
+///
+/// The one-shot functions, like `paste::get_contents()` or `copy::clear()`, connect to the
+/// compositor and bind the globals every time they are called. Programs doing many clipboard
+/// operations can open a `Connection` once and pass it to the `*_with_connection()` functions
+/// instead, saving the connection setup and a few roundtrips per operation.
+///
+/// The connection binds the highest supported version of the data-control protocol, so operations
+/// on the "primary" clipboard fail with `MissingProtocol` if the compositor only supports version
+/// 1.
+pub struct Connection {
+    pub(crate) common: CommonData,
+}
+
+/// Errors that can occur when connecting to the compositor.
+#[derive(Fail, Debug)]
+pub enum Error {
+    #[fail(display = "Couldn't connect to the Wayland compositor")]
+    WaylandConnection(#[cause] ConnectError),
+
+    #[fail(display = "Wayland compositor communication error")]
+    WaylandCommunication(#[cause] io::Error),
+
+    #[fail(display = "A required Wayland protocol ({} version {}) is not supported by the compositor",
+           name, version)]
+    MissingProtocol { name: &'static str, version: u32 },
+
+    #[fail(display = "The {} global was removed before it could be bound", name)]
+    GlobalBindFailed { name: &'static str },
+}
+
+impl From<common::Error> for Error {
+    fn from(x: common::Error) -> Self {
+        use common::Error::*;
+
+        match x {
+            WaylandConnection(err) => Error::WaylandConnection(err),
+            WaylandCommunication(err) => Error::WaylandCommunication(err),
+            MissingProtocol { name, version } => Error::MissingProtocol { name, version },
+            GlobalBindFailed { name } => Error::GlobalBindFailed { name },
+        }
+    }
+}
+
+impl Connection {
+    /// Connects to the Wayland compositor.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # extern crate wl_clipboard_rs;
+    /// # use wl_clipboard_rs::connection::Error;
+    /// # fn foo() -> Result<(), Error> {
+    /// use wl_clipboard_rs::connection::Connection;
+    ///
+    /// let mut connection = Connection::new()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[inline]
+    pub fn new() -> Result<Self, Error> {
+        Self::new_internal(None)
+    }
+
+    /// Returns the version of the data-control protocol bound by this connection.
+    ///
+    /// Version 1 doesn't support the "primary" clipboard.
+    #[inline]
+    pub fn data_control_version(&self) -> u32 {
+        self.common.clipboard_manager.as_ref().version()
+    }
+
+    // The internal function accepts the socket name, used for tests.
+    pub(crate) fn new_internal(socket_name: Option<OsString>) -> Result<Self, Error> {
+        let common = connect(1, socket_name)?;
+        Ok(Self { common })
+    }
+}
@@ -4,30 +4,44 @@ use std::{
     cell::{Cell, RefCell},
     collections::{hash_map::Entry, HashMap, HashSet},
     ffi::OsString,
-    fs::{remove_dir, remove_file, File, OpenOptions},
+    fmt,
+    fs::{self, remove_dir, remove_file, File, OpenOptions},
     io::{self, Read, Seek, SeekFrom, Write},
-    iter,
-    os::unix::io::IntoRawFd,
-    path::PathBuf,
+    os::unix::io::{AsRawFd, IntoRawFd, RawFd},
+    path::{Path, PathBuf},
     rc::Rc,
-    sync::mpsc::sync_channel,
-    thread,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::sync_channel,
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use failure::Fail;
+use libc::c_int;
 use log::info;
-use wayland_client::{ConnectError, EventQueue, Proxy};
+use nix::{
+    errno::Errno,
+    poll::{poll, PollFd, PollFlags},
+};
+use os_pipe::{pipe, PipeWriter};
+use wayland_client::{protocol::wl_seat::WlSeat, ConnectError, Display, EventQueue, Proxy};
 use wayland_protocols::wlr::unstable::data_control::v1::client::{
     zwlr_data_control_device_v1::ZwlrDataControlDeviceV1,
-    zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
     zwlr_data_control_source_v1::ZwlrDataControlSourceV1,
 };
 
 use crate::{
-    common::{self, initialize, CommonData},
-    handlers::{DataDeviceHandler, DataSourceError, DataSourceHandler},
+    common::{self, check_version, create_devices, find_seat, initialize, CommonData},
+    connection::Connection,
+    handlers::{DataSourceError, DataSourceHandler},
     seat_data::SeatData,
-    utils::{self, copy_data, is_text},
+    utils::{
+        self, copy_data, html_to_plain_text, is_text, normalize_mime_type, strip_ansi_escapes,
+        trailing_newline_len, trim_trailing_newline,
+    },
 };
 
 /// The clipboard to operate on.
@@ -66,12 +80,30 @@ pub enum MimeType {
 }
 
 /// Source for copying.
-#[derive(Clone, Eq, PartialEq, Debug, Hash, PartialOrd, Ord)]
+///
+/// The data may be empty. The MIME types are offered all the same, and pasting any of them gives
+/// zero bytes: the pasting client gets the end of file right away.
 pub enum Source {
     /// Copy contents of the standard input.
+    ///
+    /// The standard input is never touched for the other sources, so it's free for other uses.
     StdIn,
     /// Copy the given bytes.
     Bytes(Box<[u8]>),
+    /// Copy everything read from the given reader.
+    ///
+    /// The reader is drained eagerly, when the copy is prepared, rather than on the first paste.
+    Reader(Box<dyn Read + Send>),
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::StdIn => f.write_str("StdIn"),
+            Source::Bytes(data) => f.debug_tuple("Bytes").field(data).finish(),
+            Source::Reader(_) => f.write_str("Reader(..)"),
+        }
+    }
 }
 
 /// Source for copying, with a MIME type.
@@ -79,18 +111,39 @@ pub enum Source {
 /// Used for [`copy_multi`].
 ///
 /// [`copy_multi`]: fn.copy_multi.html
-#[derive(Clone, Eq, PartialEq, Debug, Hash, PartialOrd, Ord)]
+#[derive(Debug)]
 pub struct MimeSource {
     pub source: Source,
     pub mime_type: MimeType,
 }
 
+/// A MIME type that a copy would offer, as reported by [`dry_run`].
+///
+/// [`dry_run`]: fn.dry_run.html
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct DryRunOffer {
+    /// The offered MIME type.
+    pub mime_type: String,
+    /// Index of the data served for this MIME type.
+    ///
+    /// MIME types with the same index serve the same data. The data are numbered in the order
+    /// they are first offered, so the data of the first source has index 0.
+    pub data: usize,
+    /// Size of the data in bytes, or `None` if the standard input would be streamed.
+    pub size: Option<u64>,
+}
+
 /// Seat to operate on.
 #[derive(Clone, Eq, PartialEq, Debug, Hash, PartialOrd, Ord)]
 pub enum Seat {
     /// Operate on all existing seats at once.
     All,
     /// Operate on a seat with the given name.
+    ///
+    /// If no seat has this name and the name is a number, the seat with this index (counting from
+    /// 0 in the order the compositor advertised the seats, as listed by
+    /// `utils::get_seat_names()`) is used instead. Seats of version 1 count towards the index,
+    /// but picking one fails with `Error::SeatVersionUnsupported`.
     Specific(String),
 }
 
@@ -128,9 +181,15 @@ pub struct Options {
 
     /// Trim the trailing newline character before copying.
     ///
-    /// This flag is only applied for text MIME types.
+    /// Both `\n` and `\r\n` (as well as a lone `\r`) are trimmed. This flag is only applied for
+    /// text MIME types.
     trim_newline: bool,
 
+    /// Remove the ANSI escape sequences, like colors, before copying.
+    ///
+    /// This flag is only applied for text MIME types. Useful for copying colored terminal output.
+    strip_ansi: bool,
+
     /// Do not spawn a separate thread for serving copy requests.
     ///
     /// Setting this flag will result in the call to `copy()` **blocking** until all data sources
@@ -144,15 +203,126 @@ pub struct Options {
     /// that certain apps may have issues pasting when this option is used, in particular XWayland
     /// clients are known to suffer from this.
     serve_requests: ServeRequests,
+
+    /// Don't offer additional text MIME types when copying text.
+    ///
+    /// By default, when copying text, the data is also offered as `text/plain;charset=utf-8`,
+    /// `text/plain`, `STRING`, `UTF8_STRING` and `TEXT`. Some apps pick an unsuitable one of those,
+    /// so setting this flag limits the offers to the specified MIME types.
+    ///
+    /// For HTML, these additional MIME types offer a plain text version of the data with the tags
+    /// stripped, so setting this flag also disables that.
+    omit_additional_text_mime_types: bool,
+
+    /// Offer the additional text MIME types before the specified MIME types.
+    ///
+    /// By default, the specified MIME types are offered first, so that they are the preferred
+    /// choice of apps picking the first suitable offer. Some apps make a better choice from the
+    /// plain text ones, so setting this flag offers the additional text MIME types first.
+    additional_text_mime_types_first: bool,
+
+    /// Additional MIME types to offer the data under.
+    ///
+    /// The data of the first source is offered under each of these MIME types as well, unless a
+    /// source was specified with that MIME type. The MIME types must not be empty.
+    additional_mime_types: Vec<String>,
+
+    /// Additional MIME types to treat as text.
+    ///
+    /// Data of these MIME types gets its trailing newline trimmed and is offered under the
+    /// additional text MIME types, just like the MIME types recognized by `utils::is_text()`. The
+    /// parameters, like `charset`, are not taken into account.
+    text_mime_types: Vec<String>,
+
+    /// Stream the standard input directly to the paste request.
+    ///
+    /// This only takes effect when serving a single paste request. Instead of copying the
+    /// standard input into a temporary file up front and reading it back on paste, the data is
+    /// copied straight to the pasting client. This is not possible and the flag is ignored when
+    /// the data needs to be inspected beforehand: for MIME type autodetection, newline trimming
+    /// and HTML.
+    stream_stdin: bool,
+
+    /// Directory to create the temporary files in.
+    ///
+    /// By default the system temporary directory is used (`$TMPDIR` or `/tmp`).
+    temp_dir: Option<PathBuf>,
+
+    /// Keep the temporary files after serving instead of removing them.
+    ///
+    /// Data small enough to be kept in memory, as well as the streamed standard input, has no
+    /// temporary file. See `PreparedCopy::temp_files()` for getting the paths of the files.
+    keep_temp_files: bool,
+
+    /// Give up the selection after this much time, clearing the clipboard.
+    ///
+    /// The time is counted from the moment the selection is set. When serving from an external
+    /// event loop with `PreparedCopy::dispatch_pending()`, the expiry is only checked when that is
+    /// called, so the loop should wake up in time.
+    expire_after: Option<Duration>,
+
+    /// Give up the selection once no paste request has arrived for this much time.
+    ///
+    /// The time is counted from the moment the selection is set, and restarts after each served
+    /// request. Like with `expire_after`, `PreparedCopy::dispatch_pending()` only checks it when
+    /// called.
+    idle_timeout: Option<Duration>,
+}
+
+/// Sources up to this size are kept in memory rather than in a temporary file.
+const MAX_IN_MEMORY_SOURCE_SIZE: usize = 64 * 1024;
+
+/// Data backing one or more offered MIME types.
+pub(crate) enum SourceData {
+    /// Data stored in a temporary file at the given path.
+    File(PathBuf),
+    /// Data kept in memory.
+    Bytes(Box<[u8]>),
+    /// The standard input, streamed to the (single) paste request.
+    StdIn,
 }
 
+/// Data sources by the MIME types they are offered in.
+type DataSources = HashMap<String, Rc<RefCell<SourceData>>>;
+
 /// A copy operation ready to start serving requests.
+///
+/// Dropping it without calling `serve()` removes the temporary files created for the data.
 pub struct PreparedCopy {
-    should_quit: Rc<Cell<bool>>,
+    display: Display,
     queue: EventQueue,
+    selection: Selection,
+}
+
+/// A copy operation serving requests on a separate thread.
+///
+/// Dropping it or calling `cancel()` gives up the selection, stops serving requests and removes
+/// the temporary files created for the data.
+pub struct CopyGuard {
+    // Closing this pipe wakes up the serving thread and makes it stop.
+    cancel: Option<PipeWriter>,
+    thread: Option<JoinHandle<Result<(), Error>>>,
+    // Cleared once the selection is lost or the serving thread stops serving.
+    owned: Arc<AtomicBool>,
+}
+
+/// Our selection on a connection, ready to start serving requests.
+///
+/// Dropping it removes the temporary files created for the data.
+struct Selection {
+    should_quit: Rc<Cell<bool>>,
     sources: Vec<Proxy<ZwlrDataControlSourceV1>>,
-    data_paths: HashMap<String, Rc<RefCell<PathBuf>>>,
+    data_sources: Vec<DataSources>,
     error: Rc<RefCell<Option<DataSourceError>>>,
+    keep_temp_files: bool,
+    /// When to give up the selection, if ever.
+    expires_at: Option<Instant>,
+    /// How long to wait for a paste request before giving up the selection, if at all.
+    idle_timeout: Option<Duration>,
+    /// When the selection was set or the last request was served, for the idle timeout.
+    last_request: Rc<Cell<Instant>>,
+    /// Cleared once other applications have taken over all of our sources.
+    owned: Arc<AtomicBool>,
 }
 
 /// Errors that can occur for copying the source data to a temporary file.
@@ -161,6 +331,13 @@ pub enum SourceCreationError {
     #[fail(display = "Couldn't create a temporary directory")]
     TempDirCreate(#[cause] io::Error),
 
+    #[fail(display = "Couldn't create a temporary directory in {}", path)]
+    TempDirCreateIn {
+        path: String,
+        #[cause]
+        error: io::Error,
+    },
+
     #[fail(display = "Couldn't create a temporary file")]
     TempFileCreate(#[cause] io::Error),
 
@@ -170,6 +347,9 @@ pub enum SourceCreationError {
     #[fail(display = "Couldn't write to the temporary file")]
     TempFileWrite(#[cause] io::Error),
 
+    #[fail(display = "Couldn't copy data from the reader")]
+    ReaderCopy(#[cause] io::Error),
+
     #[fail(display = "Couldn't open the temporary file for newline trimming")]
     TempFileOpen(#[cause] io::Error),
 
@@ -179,11 +359,14 @@ pub enum SourceCreationError {
     #[fail(display = "Couldn't seek the temporary file for newline trimming")]
     TempFileSeek(#[cause] io::Error),
 
-    #[fail(display = "Couldn't read the last byte of the temporary file for newline trimming")]
+    #[fail(display = "Couldn't read the last bytes of the temporary file for newline trimming")]
     TempFileRead(#[cause] io::Error),
 
     #[fail(display = "Couldn't truncate the temporary file for newline trimming")]
     TempFileTruncate(#[cause] io::Error),
+
+    #[fail(display = "Couldn't read the temporary file for making the plain text version of HTML")]
+    TempFileHtmlRead(#[cause] io::Error),
 }
 
 /// Errors that can occur for copying and clearing the clipboard.
@@ -202,12 +385,30 @@ pub enum Error {
            name, version)]
     MissingProtocol { name: &'static str, version: u32 },
 
+    #[fail(display = "The {} global was removed before it could be bound", name)]
+    GlobalBindFailed { name: &'static str },
+
     #[fail(display = "The compositor does not support primary selection")]
     PrimarySelectionUnsupported,
 
     #[fail(display = "The requested seat was not found")]
     SeatNotFound,
 
+    #[fail(display = "The requested seat has version 1, which is not supported")]
+    SeatVersionUnsupported,
+
+    #[fail(display = "Several of the requested seats are the same seat")]
+    DuplicateSeat,
+
+    #[fail(display = "An additional MIME type is empty")]
+    EmptyAdditionalMimeType,
+
+    #[fail(display = "Copying with an existing connection requires the foreground mode")]
+    ForegroundRequired,
+
+    #[fail(display = "Couldn't create a pipe")]
+    PipeCreation(#[cause] io::Error),
+
     #[fail(display = "Error copying the source into a temporary file")]
     TempCopy(#[cause] SourceCreationError),
 
@@ -229,6 +430,30 @@ impl From<common::Error> for Error {
             WaylandConnection(err) => Error::WaylandConnection(err),
             WaylandCommunication(err) => Error::WaylandCommunication(err),
             MissingProtocol { name, version } => Error::MissingProtocol { name, version },
+            GlobalBindFailed { name } => Error::GlobalBindFailed { name },
+        }
+    }
+}
+
+impl From<common::DevicesError> for Error {
+    fn from(x: common::DevicesError) -> Self {
+        use common::DevicesError::*;
+
+        match x {
+            NoSeats => Error::NoSeats,
+            WaylandCommunication(err) => Error::WaylandCommunication(err),
+            PrimarySelectionUnsupported => Error::PrimarySelectionUnsupported,
+        }
+    }
+}
+
+impl From<common::SeatError> for Error {
+    fn from(x: common::SeatError) -> Self {
+        use common::SeatError::*;
+
+        match x {
+            NotFound => Error::SeatNotFound,
+            Version1 => Error::SeatVersionUnsupported,
         }
     }
 }
@@ -256,13 +481,23 @@ impl Options {
 
     /// Sets the flag for trimming the trailing newline.
     ///
-    /// This flag is only applied for text MIME types.
+    /// Both `\n` and `\r\n` (as well as a lone `\r`) are trimmed. This flag is only applied for
+    /// text MIME types.
     #[inline]
     pub fn trim_newline(&mut self, trim_newline: bool) -> &mut Self {
         self.trim_newline = trim_newline;
         self
     }
 
+    /// Sets the flag for removing the ANSI escape sequences, like colors.
+    ///
+    /// This flag is only applied for text MIME types. Useful for copying colored terminal output.
+    #[inline]
+    pub fn strip_ansi(&mut self, strip_ansi: bool) -> &mut Self {
+        self.strip_ansi = strip_ansi;
+        self
+    }
+
     /// Sets the flag for not spawning a separate thread for serving copy requests.
     ///
     /// Setting this flag will result in the call to `copy()` **blocking** until all data sources
@@ -285,6 +520,114 @@ impl Options {
         self
     }
 
+    /// Sets the flag for not offering additional text MIME types when copying text.
+    ///
+    /// By default, when copying text, the data is also offered as `text/plain;charset=utf-8`,
+    /// `text/plain`, `STRING`, `UTF8_STRING` and `TEXT`. Some apps pick an unsuitable one of those,
+    /// so setting this flag limits the offers to the specified MIME types.
+    ///
+    /// For HTML, these additional MIME types offer a plain text version of the data with the tags
+    /// stripped, so setting this flag also disables that.
+    #[inline]
+    pub fn omit_additional_text_mime_types(&mut self,
+                                           omit_additional_text_mime_types: bool)
+                                           -> &mut Self {
+        self.omit_additional_text_mime_types = omit_additional_text_mime_types;
+        self
+    }
+
+    /// Sets the flag for offering the additional text MIME types before the specified ones.
+    ///
+    /// By default, the specified MIME types are offered first, so that they are the preferred
+    /// choice of apps picking the first suitable offer. Some apps make a better choice from the
+    /// plain text ones, so setting this flag offers the additional text MIME types first.
+    #[inline]
+    pub fn additional_text_mime_types_first(&mut self,
+                                            additional_text_mime_types_first: bool)
+                                            -> &mut Self {
+        self.additional_text_mime_types_first = additional_text_mime_types_first;
+        self
+    }
+
+    /// Sets the additional MIME types to offer the data under.
+    ///
+    /// The data of the first source is offered under each of these MIME types as well, unless a
+    /// source was specified with that MIME type. The MIME types must not be empty.
+    #[inline]
+    pub fn additional_mime_types(&mut self, additional_mime_types: Vec<String>) -> &mut Self {
+        self.additional_mime_types = additional_mime_types;
+        self
+    }
+
+    /// Sets the additional MIME types to treat as text.
+    ///
+    /// Data of these MIME types gets its trailing newline trimmed and is offered under the
+    /// additional text MIME types, just like the MIME types recognized by `utils::is_text()`. The
+    /// parameters, like `charset`, are not taken into account.
+    #[inline]
+    pub fn text_mime_types(&mut self, text_mime_types: Vec<String>) -> &mut Self {
+        self.text_mime_types = text_mime_types;
+        self
+    }
+
+    /// Sets the flag for streaming the standard input directly to the paste request.
+    ///
+    /// This only takes effect when serving a single paste request. Instead of copying the
+    /// standard input into a temporary file up front and reading it back on paste, the data is
+    /// copied straight to the pasting client. This is not possible and the flag is ignored when
+    /// the data needs to be inspected beforehand: for MIME type autodetection, newline trimming
+    /// and HTML.
+    ///
+    /// Note that the standard input is not read until the paste, so whatever is writing into it
+    /// may block until then.
+    #[inline]
+    pub fn stream_stdin(&mut self, stream_stdin: bool) -> &mut Self {
+        self.stream_stdin = stream_stdin;
+        self
+    }
+
+    /// Sets the directory to create the temporary files in.
+    ///
+    /// By default the system temporary directory is used (`$TMPDIR` or `/tmp`). Setting this can
+    /// be useful when that is too small for the copied data.
+    #[inline]
+    pub fn temp_dir(&mut self, temp_dir: PathBuf) -> &mut Self {
+        self.temp_dir = Some(temp_dir);
+        self
+    }
+
+    /// Sets the flag for keeping the temporary files after serving instead of removing them.
+    ///
+    /// Data small enough to be kept in memory, as well as the streamed standard input, has no
+    /// temporary file. See `PreparedCopy::temp_files()` for getting the paths of the files.
+    #[inline]
+    pub fn keep_temp_files(&mut self, keep_temp_files: bool) -> &mut Self {
+        self.keep_temp_files = keep_temp_files;
+        self
+    }
+
+    /// Sets the time after which to give up the selection, clearing the clipboard.
+    ///
+    /// The time is counted from the moment the selection is set. When serving from an external
+    /// event loop with `PreparedCopy::dispatch_pending()`, the expiry is only checked when that is
+    /// called, so the loop should wake up in time.
+    #[inline]
+    pub fn expire_after(&mut self, expire_after: Duration) -> &mut Self {
+        self.expire_after = Some(expire_after);
+        self
+    }
+
+    /// Sets the time after which to give up the selection if no paste request arrives.
+    ///
+    /// The time is counted from the moment the selection is set, and restarts after each served
+    /// request. Like with `expire_after()`, `PreparedCopy::dispatch_pending()` only checks it when
+    /// called.
+    #[inline]
+    pub fn idle_timeout(&mut self, idle_timeout: Duration) -> &mut Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
     /// Invokes the copy operation. See `copy()`.
     ///
     /// # Examples
@@ -386,52 +729,245 @@ impl Options {
     pub fn prepare_copy_multi(self, sources: Vec<MimeSource>) -> Result<PreparedCopy, Error> {
         prepare_copy_multi(self, sources)
     }
+
+    /// Invokes the copy_guarded function.
+    ///
+    /// See `copy_guarded()` for details.
+    #[inline]
+    pub fn copy_guarded(self, source: Source, mime_type: MimeType) -> Result<CopyGuard, Error> {
+        copy_guarded(self, source, mime_type)
+    }
+
+    /// Invokes the copy_multi_guarded function.
+    ///
+    /// See `copy_multi_guarded()` for details.
+    #[inline]
+    pub fn copy_multi_guarded(self, sources: Vec<MimeSource>) -> Result<CopyGuard, Error> {
+        copy_multi_guarded(self, sources)
+    }
 }
 
 impl PreparedCopy {
     /// Starts serving copy requests.
     ///
     /// This function **blocks** until all requests are served or the clipboard is taken over by
-    /// some other application.
-    pub fn serve(mut self) -> Result<(), Error> {
-        // Loop until we're done.
-        while !self.should_quit.get() {
-            self.queue.dispatch().map_err(Error::WaylandCommunication)?;
+    /// some other application. In between requests it sleeps waiting on the Wayland connection
+    /// (with a timeout if `Options::expire_after()` or `Options::idle_timeout()` is set), so
+    /// serving uses no CPU while idle.
+    pub fn serve(self) -> Result<(), Error> {
+        let PreparedCopy { display,
+                           mut queue,
+                           selection, } = self;
+        selection.serve(&mut queue, &display, None)
+    }
 
-            // Check if all sources have been destroyed.
-            let all_destroyed = self.sources.iter().all(|x| !x.is_alive());
-            if all_destroyed {
-                self.should_quit.set(true);
+    /// Returns the file descriptor of the Wayland connection.
+    ///
+    /// Together with `dispatch_pending()`, this allows serving copy requests from an existing event
+    /// loop instead of blocking in `serve()`: poll the file descriptor for readability and call
+    /// `dispatch_pending()` whenever it is readable. The file descriptor should only be used for
+    /// polling, never read from or written to directly.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # extern crate wl_clipboard_rs;
+    /// # extern crate nix;
+    /// # extern crate failure;
+    /// # use failure::Error;
+    /// # fn foo() -> Result<(), Error> {
+    /// use nix::poll::{poll, PollFd, PollFlags};
+    /// use wl_clipboard_rs::copy::{MimeType, Options, Source};
+    ///
+    /// let mut opts = Options::new();
+    /// opts.foreground(true);
+    /// let mut prepared_copy = opts.prepare_copy(Source::Bytes([1, 2, 3][..].into()),
+    ///                                           MimeType::Autodetect)?;
+    ///
+    /// loop {
+    ///     let mut fds = [PollFd::new(prepared_copy.connection_fd(), PollFlags::POLLIN)];
+    ///     poll(&mut fds, -1)?;
+    ///
+    ///     if prepared_copy.dispatch_pending()? {
+    ///         break;
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[inline]
+    pub fn connection_fd(&self) -> RawFd {
+        self.queue.get_connection_fd()
+    }
+
+    /// Returns the paths of the temporary files backing the copied data.
+    ///
+    /// Data small enough to be kept in memory, as well as the streamed standard input, has no
+    /// temporary file.
+    pub fn temp_files(&self) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        for data_source in self.selection.data_sources.iter().flat_map(HashMap::values) {
+            if let SourceData::File(path) = &*data_source.borrow() {
+                if !paths.contains(path) {
+                    paths.push(path.clone());
+                }
             }
         }
 
-        // Clean up the temp file and directory.
-        //
-        // We want to try cleaning up all files and folders, so if any errors occur in process,
-        // collect them into a vector without interruption, and then return the first one.
-        let mut results = Vec::new();
-        let mut dropped = HashSet::new();
-        for data_path in self.data_paths.values() {
-            let buf = data_path.as_ptr();
-            // data_paths can contain duplicate items, we want to free each only once.
-            if dropped.contains(&buf) {
-                continue;
-            };
-            dropped.insert(buf);
+        paths
+    }
+
+    /// Serves the copy requests that have arrived, without blocking.
+    ///
+    /// Returns `true` once all requests are served or the clipboard is taken over by some other
+    /// application. At that point the temporary files are already removed and the prepared copy
+    /// can be dropped. See `connection_fd()` for an example.
+    pub fn dispatch_pending(&mut self) -> Result<bool, Error> {
+        self.selection.dispatch_pending(&mut self.queue, &self.display)
+    }
+
+    /// Serves copy requests until done or until `cancel_fd` becomes readable or is closed.
+    ///
+    /// Cancelling gives up the selection and removes the temporary files, if any. This is useful
+    /// for stopping the serving from the outside, for example on a signal via a `signalfd`.
+    pub fn serve_until_cancelled(self, cancel_fd: RawFd) -> Result<(), Error> {
+        let PreparedCopy { display,
+                           mut queue,
+                           selection, } = self;
+        selection.serve(&mut queue, &display, Some(cancel_fd))
+    }
+}
+
+impl CopyGuard {
+    /// Checks whether the selection is still ours.
+    ///
+    /// This becomes `false` once serving stops: when another application sets the selection,
+    /// when all requests have been served or when the selection expires. With
+    /// `ClipboardType::Both`, the selection stays ours until both clipboards are taken over.
+    ///
+    /// Useful for showing that the copy was overridden.
+    #[inline]
+    pub fn is_owned(&self) -> bool {
+        self.owned.load(Ordering::SeqCst)
+    }
 
-            let mut data_path = data_path.borrow_mut();
-            match remove_file(&*data_path).map_err(Error::TempFileRemove) {
-                Ok(()) => {
-                    data_path.pop();
-                    results.push(remove_dir(&*data_path).map_err(Error::TempDirRemove));
+    /// Gives up the selection and stops serving requests.
+    ///
+    /// Waits for the serving thread to finish and returns the error that occurred while serving
+    /// the requests, if any.
+    pub fn cancel(mut self) -> Result<(), Error> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Result<(), Error> {
+        drop(self.cancel.take());
+
+        match self.thread.take() {
+            Some(thread) => thread.join().unwrap(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for CopyGuard {
+    fn drop(&mut self) {
+        // There's nobody listening for errors at this point, just drop it.
+        drop(self.stop());
+    }
+}
+
+impl Selection {
+    // Both ways of dispatching block until there's something to do, be it events, cancellation
+    // or the deadline, rather than waking up periodically.
+    fn serve(mut self,
+             queue: &mut EventQueue,
+             display: &Display,
+             cancel_fd: Option<RawFd>)
+             -> Result<(), Error> {
+        // Loop until we're done.
+        while !self.should_quit.get() {
+            let deadline = self.deadline();
+            if cancel_fd.is_none() && deadline.is_none() {
+                queue.dispatch().map_err(Error::WaylandCommunication)?;
+            } else {
+                let stopped = dispatch_until(queue, display, cancel_fd, deadline)
+                              .map_err(Error::WaylandCommunication)?;
+                if stopped {
+                    break;
                 }
-                result @ Err(_) => results.push(result),
             }
+
+            self.check_sources();
         }
 
-        // Return the error, if any.
-        let result: Result<_, _> = results.into_iter().collect();
-        result?;
+        // When stopped early, the connection may stay around for a while, so send the requests
+        // now.
+        self.finish(Some(display))
+    }
+
+    // Reads and dispatches the available events without blocking. Returns `true` once done.
+    fn dispatch_pending(&mut self,
+                        queue: &mut EventQueue,
+                        display: &Display)
+                        -> Result<bool, Error> {
+        if let Some(guard) = queue.prepare_read() {
+            match guard.read_events() {
+                Ok(_) => {}
+                // Nothing to read.
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(Error::WaylandCommunication(err)),
+            }
+        }
+
+        queue.dispatch_pending()
+             .map_err(Error::WaylandCommunication)?;
+        self.check_sources();
+
+        if self.should_quit.get() || has_passed(self.deadline()) {
+            self.finish(Some(display))?;
+            return Ok(true);
+        }
+
+        // Send the replies, if any, before the caller goes back to polling.
+        match display.flush() {
+            Ok(()) => Ok(false),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(Error::WaylandCommunication(err)),
+        }
+    }
+
+    // Returns when to give up the selection: at the expiry or after being idle, whichever is first.
+    fn deadline(&self) -> Option<Instant> {
+        let idle_deadline = self.idle_timeout.map(|x| self.last_request.get() + x);
+        match (self.expires_at, idle_deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    // Checks if all sources have been destroyed.
+    fn check_sources(&self) {
+        let all_destroyed = self.sources.iter().all(|x| !x.is_alive());
+        if all_destroyed {
+            self.should_quit.set(true);
+        }
+    }
+
+    // Gives up the selection and cleans up after serving requests.
+    //
+    // If `display` is given, the requests are sent right away.
+    fn finish(&mut self, display: Option<&Display>) -> Result<(), Error> {
+        // Give up the selection in case the connection stays open for other operations.
+        for source in self.sources.drain(..).filter(|x| x.is_alive()) {
+            ZwlrDataControlSourceV1::from(source).destroy();
+        }
+
+        if let Some(display) = display {
+            display.flush().map_err(Error::WaylandCommunication)?;
+        }
+
+        // Clean up the temp files and directories.
+        self.remove_temp_files()?;
 
         // Check if an error occurred during data transfer.
         if let Some(err) = self.error.borrow_mut().take() {
@@ -440,36 +976,288 @@ impl PreparedCopy {
 
         Ok(())
     }
+
+    // Removes the temp files of all data sources, returning the first error.
+    fn remove_temp_files(&mut self) -> Result<(), Error> {
+        if self.keep_temp_files {
+            self.data_sources.clear();
+            return Ok(());
+        }
+
+        self.data_sources
+            .iter_mut()
+            .map(remove_temp_files)
+            .fold(Ok(()), Result::and)
+    }
+}
+
+impl Drop for Selection {
+    fn drop(&mut self) {
+        // Make sure the temp files are cleaned up even if serving was never started or was
+        // interrupted by an error or a panic. This is a no-op after a successful `serve()`.
+        drop(self.remove_temp_files());
+    }
+}
+
+/// Dispatches the events like `EventQueue::dispatch()`, unless `cancel_fd` becomes readable or is
+/// closed or `deadline` passes first.
+///
+/// Returns `true` if stopped by `cancel_fd` or `deadline`.
+fn dispatch_until(queue: &mut EventQueue,
+                  display: &Display,
+                  cancel_fd: Option<RawFd>,
+                  deadline: Option<Instant>)
+                  -> io::Result<bool> {
+    // Check the deadline up front in case the events never stop coming.
+    if has_passed(deadline) {
+        return Ok(true);
+    }
+
+    let guard = match queue.prepare_read() {
+        Some(guard) => guard,
+        // There are events waiting to be dispatched already.
+        None => return queue.dispatch_pending().map(|_| false),
+    };
+
+    display.flush()?;
+
+    let mut fds = vec![PollFd::new(queue.get_connection_fd(), PollFlags::POLLIN)];
+    if let Some(cancel_fd) = cancel_fd {
+        fds.push(PollFd::new(cancel_fd, PollFlags::POLLIN));
+    }
+
+    loop {
+        // Wake up at the deadline, rounding up so as not to wake up just before it.
+        let timeout = deadline.map(|x| {
+                                  let left = x.saturating_duration_since(Instant::now());
+                                  let round_up = left.subsec_nanos() % 1_000_000 != 0;
+                                  let millis = left.as_millis() + u128::from(round_up);
+                                  millis.min(c_int::MAX as u128) as c_int
+                              })
+                              .unwrap_or(-1);
+
+        match poll(&mut fds, timeout) {
+            Ok(_) => break,
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(nix::Error::Sys(err)) => return Err(err.into()),
+            Err(_) => unreachable!(),
+        }
+    }
+
+    let cancelled = fds.get(1)
+                       .map(|x| !x.revents().unwrap_or_else(PollFlags::empty).is_empty())
+                       .unwrap_or(false);
+    if cancelled || has_passed(deadline) {
+        guard.cancel();
+        return Ok(true);
+    }
+
+    match guard.read_events() {
+        Ok(_) => {}
+        // Nothing to read after all.
+        Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {}
+        Err(err) => return Err(err),
+    }
+
+    queue.dispatch_pending().map(|_| false)
+}
+
+/// Returns `true` if `deadline` is set and has passed.
+fn has_passed(deadline: Option<Instant>) -> bool {
+    deadline.map(|x| Instant::now() >= x).unwrap_or(false)
+}
+
+/// Removes all temporary files backing `data_sources`, leaving it empty.
+///
+/// We want to try cleaning up all files and folders, so if any errors occur in process, collect
+/// them into a vector without interruption, and then return the first one.
+fn remove_temp_files(data_sources: &mut DataSources) -> Result<(), Error> {
+    let mut results = Vec::new();
+    let mut dropped = HashSet::new();
+    for (_, data_source) in data_sources.drain() {
+        let buf = data_source.as_ptr();
+        // data_sources can contain duplicate items, we want to free each only once.
+        if dropped.contains(&buf) {
+            continue;
+        };
+        dropped.insert(buf);
+
+        // In-memory sources don't need any cleaning up.
+        if let SourceData::File(ref mut data_path) = *data_source.borrow_mut() {
+            results.push(remove_temp_file(data_path));
+        }
+    }
+
+    results.into_iter().collect()
+}
+
+/// Removes a temporary file created by `make_source()` along with its directory.
+fn remove_temp_file(data_path: &mut PathBuf) -> Result<(), Error> {
+    remove_file(&*data_path).map_err(Error::TempFileRemove)?;
+    data_path.pop();
+    remove_dir(&*data_path).map_err(Error::TempDirRemove)
+}
+
+/// Checks if the given MIME type is text or one of the MIME types to treat as text.
+fn is_text_or_listed(mime_type: &str, text_mime_types: &[String]) -> bool {
+    let base = |x: &str| normalize_mime_type(x).split(';').next().unwrap().to_string();
+
+    is_text(mime_type) || text_mime_types.iter().any(|x| base(x) == base(mime_type))
 }
 
 fn make_source(source: Source,
                mime_type: MimeType,
-               trim_newline: bool)
-               -> Result<(String, PathBuf), SourceCreationError> {
-    let temp_dir = tempfile::tempdir().map_err(SourceCreationError::TempDirCreate)?;
-    let mut temp_filename = temp_dir.into_path();
-    temp_filename.push("stdin");
-    info!("Temp filename: {}", temp_filename.to_string_lossy());
-    let mut temp_file = File::create(&temp_filename).map_err(SourceCreationError::TempFileCreate)?;
+               trim_newline: bool,
+               strip_ansi: bool,
+               text_mime_types: &[String],
+               stream_stdin: bool,
+               temp_dir: Option<&Path>)
+               -> Result<(String, SourceData), SourceCreationError> {
+    match source {
+        Source::StdIn
+            if stream_stdin
+               && can_stream(&mime_type, trim_newline || strip_ansi, text_mime_types) =>
+        {
+            // See can_stream().
+            let mime_type = base_mime_type(mime_type, || unreachable!());
+            Ok((mime_type, SourceData::StdIn))
+        }
+        Source::Bytes(data) if data.len() <= MAX_IN_MEMORY_SOURCE_SIZE => {
+            Ok(make_memory_source(data, mime_type, trim_newline, strip_ansi, text_mime_types))
+        }
+        Source::Reader(mut reader) => {
+            // Small data is kept in memory, so read a bit more than that to tell them apart.
+            let mut data = Vec::new();
+            (&mut reader).take(MAX_IN_MEMORY_SOURCE_SIZE as u64 + 1)
+                         .read_to_end(&mut data)
+                         .map_err(SourceCreationError::ReaderCopy)?;
+
+            if data.len() <= MAX_IN_MEMORY_SOURCE_SIZE {
+                Ok(make_memory_source(data.into(),
+                                      mime_type,
+                                      trim_newline,
+                                      strip_ansi,
+                                      text_mime_types))
+            } else {
+                let source = Source::Reader(Box::new(io::Cursor::new(data).chain(reader)));
+                let (mime_type, data_path) = make_file_source(source,
+                                                              mime_type,
+                                                              trim_newline,
+                                                              strip_ansi,
+                                                              text_mime_types,
+                                                              temp_dir)?;
+                Ok((mime_type, SourceData::File(data_path)))
+            }
+        }
+        source => {
+            let (mime_type, data_path) = make_file_source(source,
+                                                          mime_type,
+                                                          trim_newline,
+                                                          strip_ansi,
+                                                          text_mime_types,
+                                                          temp_dir)?;
+            Ok((mime_type, SourceData::File(data_path)))
+        }
+    }
+}
 
-    if let Source::Bytes(data) = source {
-        temp_file.write_all(&data)
-                 .map_err(SourceCreationError::TempFileWrite)?;
-    } else {
-        // Copy the standard input into the target file.
-        copy_data(None, temp_file.into_raw_fd(), true).map_err(SourceCreationError::DataCopy)?;
+/// Checks if the standard input can be streamed without looking at the data beforehand.
+///
+/// `modify_text` tells if text needs to be modified, like with newline trimming.
+fn can_stream(mime_type: &MimeType, modify_text: bool, text_mime_types: &[String]) -> bool {
+    match mime_type {
+        MimeType::Autodetect => false,
+        MimeType::Text => !modify_text,
+        MimeType::Specific(mime_type) => {
+            let needs_modifying = modify_text && is_text_or_listed(mime_type, text_mime_types);
+            !needs_modifying && !is_html(mime_type)
+        }
     }
+}
 
+/// Returns the MIME type to offer the data under, calling `autodetect` if it's to be detected.
+fn base_mime_type(mime_type: MimeType, autodetect: impl FnOnce() -> String) -> String {
     let mime_type = match mime_type {
-        MimeType::Autodetect => tree_magic::from_filepath(&temp_filename),
+        MimeType::Autodetect => autodetect(),
         MimeType::Text => "text/plain".to_string(),
         MimeType::Specific(mime_type) => mime_type,
     };
 
     info!("Base MIME type: {}", mime_type);
+    mime_type
+}
+
+fn make_memory_source(data: Box<[u8]>,
+                      mime_type: MimeType,
+                      trim_newline: bool,
+                      strip_ansi: bool,
+                      text_mime_types: &[String])
+                      -> (String, SourceData) {
+    let mut data = data.into_vec();
+
+    let mime_type = base_mime_type(mime_type, || tree_magic::from_u8(&data));
+
+    // Strip the escape sequences first, they may come after the newline.
+    if strip_ansi && is_text_or_listed(&mime_type, text_mime_types) {
+        data = strip_ansi_escapes(&data);
+    }
+
+    // Trim the trailing newline if needed.
+    if trim_newline && is_text_or_listed(&mime_type, text_mime_types) {
+        trim_trailing_newline(&mut data);
+    }
+
+    (mime_type, SourceData::Bytes(data.into()))
+}
+
+fn make_file_source(source: Source,
+                    mime_type: MimeType,
+                    trim_newline: bool,
+                    strip_ansi: bool,
+                    text_mime_types: &[String],
+                    temp_dir: Option<&Path>)
+                    -> Result<(String, PathBuf), SourceCreationError> {
+    let temp_dir = match temp_dir {
+        Some(path) => {
+            tempfile::tempdir_in(path).map_err(|error| {
+                                          SourceCreationError::TempDirCreateIn {
+                                              path: path.to_string_lossy().into_owned(),
+                                              error,
+                                          }
+                                      })?
+        }
+        None => tempfile::tempdir().map_err(SourceCreationError::TempDirCreate)?,
+    };
+    let mut temp_filename = temp_dir.into_path();
+    temp_filename.push("stdin");
+    info!("Temp filename: {}", temp_filename.to_string_lossy());
+    let mut temp_file = File::create(&temp_filename).map_err(SourceCreationError::TempFileCreate)?;
+
+    match source {
+        Source::StdIn => {
+            // Copy the standard input into the target file.
+            copy_data(None, temp_file.into_raw_fd(), true).map_err(SourceCreationError::DataCopy)?;
+        }
+        Source::Bytes(data) => {
+            temp_file.write_all(&data)
+                     .map_err(SourceCreationError::TempFileWrite)?;
+        }
+        Source::Reader(mut reader) => {
+            io::copy(&mut reader, &mut temp_file).map_err(SourceCreationError::ReaderCopy)?;
+        }
+    }
+
+    let mime_type = base_mime_type(mime_type, || tree_magic::from_filepath(&temp_filename));
+
+    // Strip the escape sequences first, they may come after the newline.
+    if strip_ansi && is_text_or_listed(&mime_type, text_mime_types) {
+        let data = fs::read(&temp_filename).map_err(SourceCreationError::TempFileRead)?;
+        fs::write(&temp_filename, strip_ansi_escapes(&data))
+            .map_err(SourceCreationError::TempFileWrite)?;
+    }
 
     // Trim the trailing newline if needed.
-    if trim_newline && is_text(&mime_type) {
+    if trim_newline && is_text_or_listed(&mime_type, text_mime_types) {
         let mut temp_file = OpenOptions::new().read(true)
                                               .write(true)
                                               .open(&temp_filename)
@@ -478,14 +1266,18 @@ fn make_source(source: Source,
                                 .map_err(SourceCreationError::TempFileMetadata)?;
         let length = metadata.len();
         if length > 0 {
-            temp_file.seek(SeekFrom::End(-1))
-                     .map_err(SourceCreationError::TempFileSeek)?;
+            // Read up to two last bytes to account for "\r\n".
+            let mut buf = [0; 2];
+            let buf = &mut buf[..length.min(2) as usize];
 
-            let mut buf = [0];
-            temp_file.read_exact(&mut buf)
+            temp_file.seek(SeekFrom::End(-(buf.len() as i64)))
+                     .map_err(SourceCreationError::TempFileSeek)?;
+            temp_file.read_exact(buf)
                      .map_err(SourceCreationError::TempFileRead)?;
-            if buf[0] == b'\n' {
-                temp_file.set_len(length - 1)
+
+            let newline_len = trailing_newline_len(buf) as u64;
+            if newline_len > 0 {
+                temp_file.set_len(length - newline_len)
                          .map_err(SourceCreationError::TempFileTruncate)?;
             }
         }
@@ -494,92 +1286,70 @@ fn make_source(source: Source,
     Ok((mime_type, temp_filename))
 }
 
-fn get_devices(
-    primary: bool,
-    seat: Seat,
-    socket_name: Option<OsString>)
-    -> Result<(EventQueue, ZwlrDataControlManagerV1, Vec<ZwlrDataControlDeviceV1>), Error> {
-    let CommonData { mut queue,
-                     clipboard_manager,
-                     seats, } = initialize(primary, socket_name)?;
-
-    // Check if there are no seats.
-    if seats.borrow_mut().is_empty() {
-        return Err(Error::NoSeats);
-    }
-
-    let supports_primary = Rc::new(Cell::new(false));
-
-    // Go through the seats and get their data devices.
-    for seat in &*seats.borrow_mut() {
-        // TODO: fast path here if all seats.
-        let handler = DataDeviceHandler::new(seat.clone(), primary, supports_primary.clone());
-        let device =
-            clipboard_manager.get_data_device(seat, |device| device.implement(handler, ()))
-                             .unwrap();
-
-        let seat_data = seat.as_ref().user_data::<RefCell<SeatData>>().unwrap();
-        seat_data.borrow_mut().set_device(Some(device));
-    }
+/// Makes an in-memory plain text version of an HTML source.
+fn make_plain_text_source(html: &SourceData) -> Result<SourceData, SourceCreationError> {
+    let text = match *html {
+        SourceData::File(ref data_path) => {
+            let data = fs::read(data_path).map_err(SourceCreationError::TempFileHtmlRead)?;
+            html_to_plain_text(&String::from_utf8_lossy(&data))
+        }
+        SourceData::Bytes(ref data) => html_to_plain_text(&String::from_utf8_lossy(data)),
+        // See can_stream().
+        SourceData::StdIn => unreachable!("HTML is never streamed"),
+    };
 
-    // Retrieve all seat names.
-    queue.sync_roundtrip()
-         .map_err(Error::WaylandCommunication)?;
+    Ok(SourceData::Bytes(text.into_bytes().into()))
+}
 
-    // Check if the compositor supports primary selection.
-    if primary && !supports_primary.get() {
-        return Err(Error::PrimarySelectionUnsupported);
-    }
-
-    // Figure out which devices we're interested in.
-    let devices = seats.borrow_mut()
-                       .iter()
-                       .map(|seat| {
-                           seat.as_ref()
-                               .user_data::<RefCell<SeatData>>()
-                               .unwrap()
-                               .borrow()
-                       })
-                       .filter_map(|data| {
-                           let SeatData { name, device, .. } = &*data;
-
-                           if device.is_none() {
-                               // Can't handle seats without devices.
-                               return None;
-                           }
-
-                           let device = device.as_ref().cloned().unwrap();
-
-                           match seat {
-                               Seat::All => {
-                                   // If no seat was specified, handle all of them.
-                                   return Some(device);
-                               }
-                               Seat::Specific(ref desired_name) => {
-                                   if let Some(name) = name {
-                                       if name == desired_name {
-                                           return Some(device);
-                                       }
-                                   }
-                               }
-                           }
+/// Checks if the given MIME type represents HTML.
+fn is_html(mime_type: &str) -> bool {
+    mime_type == "text/html" || mime_type.starts_with("text/html;")
+}
 
-                           None
-                       })
-                       .collect::<Vec<_>>();
+fn get_devices(common: &mut CommonData,
+               primary: bool,
+               seat: Seat)
+               -> Result<Vec<ZwlrDataControlDeviceV1>, Error> {
+    create_devices(common, primary)?;
+    let devices = find_devices(common, &seat)?;
 
     // If we didn't find the seat, print an error message and exit.
     if devices.is_empty() {
         return Err(Error::SeatNotFound);
     }
 
-    Ok((queue, clipboard_manager, devices))
+    Ok(devices)
+}
+
+// Returns the data devices of the seats we're interested in.
+fn find_devices(common: &CommonData, seat: &Seat) -> Result<Vec<ZwlrDataControlDeviceV1>, Error> {
+    let seats = common.seats.borrow();
+    let device = |seat: &WlSeat| {
+        seat.as_ref()
+            .user_data::<RefCell<SeatData>>()
+            .unwrap()
+            .borrow()
+            .device
+            .clone()
+    };
+
+    match seat {
+        // If no seat was specified, handle all of them.
+        Seat::All => Ok(seats.iter().filter_map(device).collect()),
+        // Can't handle seats without devices.
+        Seat::Specific(name) => {
+            let seat = find_seat(&seats, &common.seat_globals.borrow(), name)?;
+            Ok(device(seat).into_iter().collect())
+        }
+    }
 }
 
 /// Clears the clipboard for the given seat.
 ///
 /// If `seat` is `None`, clears clipboards of all existing seats.
 ///
+/// Copying new contents doesn't need a clear beforehand, as they replace the old ones directly.
+///
 /// # Examples
 ///
 /// ```no_run
@@ -597,12 +1367,47 @@ pub fn clear(clipboard: ClipboardType, seat: Seat) -> Result<(), Error> {
     clear_internal(clipboard, seat, None)
 }
 
+/// Clears the clipboard for the given seat using an existing connection.
+///
+/// This is the same as `clear()`, except that `connection` is used instead of connecting to the
+/// compositor anew.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate wl_clipboard_rs;
+/// # extern crate failure;
+/// # use failure::Error;
+/// # fn foo() -> Result<(), Error> {
+/// use wl_clipboard_rs::{connection::Connection,
+///                       copy::{clear_with_connection, ClipboardType, Seat}};
+///
+/// let mut connection = Connection::new()?;
+/// clear_with_connection(&mut connection, ClipboardType::Regular, Seat::All)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn clear_with_connection(connection: &mut Connection,
+                             clipboard: ClipboardType,
+                             seat: Seat)
+                             -> Result<(), Error> {
+    let primary = clipboard != ClipboardType::Regular;
+    check_version(&connection.common, primary)?;
+    clear_on(&mut connection.common, clipboard, seat)
+}
+
 pub(crate) fn clear_internal(clipboard: ClipboardType,
                              seat: Seat,
                              socket_name: Option<OsString>)
                              -> Result<(), Error> {
     let primary = clipboard != ClipboardType::Regular;
-    let (mut queue, _, devices) = get_devices(primary, seat, socket_name)?;
+    let mut common = initialize(primary, socket_name)?;
+    clear_on(&mut common, clipboard, seat)
+}
+
+fn clear_on(common: &mut CommonData, clipboard: ClipboardType, seat: Seat) -> Result<(), Error> {
+    let primary = clipboard != ClipboardType::Regular;
+    let devices = get_devices(common, primary, seat)?;
 
     for device in devices {
         if clipboard == ClipboardType::Primary || clipboard == ClipboardType::Both {
@@ -614,7 +1419,8 @@ pub(crate) fn clear_internal(clipboard: ClipboardType,
     }
 
     // We're clearing the clipboard so just do one roundtrip and quit.
-    queue.sync_roundtrip()
+    common.queue
+          .sync_roundtrip()
          .map_err(Error::WaylandCommunication)?;
 
     Ok(())
@@ -671,7 +1477,7 @@ pub fn prepare_copy(options: Options,
 ///
 /// If multiple sources specify the same MIME type, the first one is offered. If one of the MIME
 /// types is text, all automatically added plain text offers will fall back to the first source
-/// with a text MIME type.
+/// with a text MIME type. If that source is HTML, they offer a plain text version of it instead.
 ///
 /// This function can be used instead of `copy()` when it's desirable to separately prepare the
 /// copy operation, handle any errors that this may produce, and then start the serving loop,
@@ -711,128 +1517,256 @@ pub fn prepare_copy_multi(options: Options,
     prepare_copy_internal(options, sources, None)
 }
 
-fn prepare_copy_internal(options: Options,
-                         sources: Vec<MimeSource>,
-                         socket_name: Option<OsString>)
-                         -> Result<PreparedCopy, Error> {
-    let Options { clipboard,
-                  seat,
-                  trim_newline,
-                  serve_requests,
-                  .. } = options;
+pub(crate) fn prepare_copy_internal(options: Options,
+                                    sources: Vec<MimeSource>,
+                                    socket_name: Option<OsString>)
+                                    -> Result<PreparedCopy, Error> {
+    check_options(&options)?;
 
-    let primary = clipboard != ClipboardType::Regular;
-    let (queue, clipboard_manager, devices) = get_devices(primary, seat, socket_name)?;
-
-    // Collect the source data to copy.
-    let data_paths = {
-        let mut data_paths = HashMap::new();
-        let mut text_data_path = None;
-        for MimeSource { source, mime_type } in sources.into_iter() {
-            let (mime_type, mut data_path) =
-                make_source(source, mime_type, trim_newline).map_err(Error::TempCopy)?;
-
-            let mime_type_is_text = is_text(&mime_type);
-
-            match data_paths.entry(mime_type) {
-                Entry::Occupied(_) => {
-                    // This MIME type has already been specified, so ignore it.
-                    remove_file(&*data_path).map_err(Error::TempFileRemove)?;
-                    data_path.pop();
-                    remove_dir(&*data_path).map_err(Error::TempDirRemove)?;
-                }
-                Entry::Vacant(entry) => {
-                    let data_path = Rc::new(RefCell::new(data_path));
+    let primary = options.clipboard != ClipboardType::Regular;
+    let mut common = initialize(primary, socket_name)?;
+    let selection = select(&mut common, options, sources)?;
 
-                    if text_data_path.is_none() && mime_type_is_text {
-                        text_data_path = Some(data_path.clone());
-                    }
+    Ok(PreparedCopy { display: common.display,
+                      queue: common.queue,
+                      selection })
+}
 
-                    entry.insert(data_path);
-                }
-            }
-        }
+// Checks the options that can be checked before connecting to the compositor.
+fn check_options(options: &Options) -> Result<(), Error> {
+    if options.additional_mime_types.iter().any(String::is_empty) {
+        return Err(Error::EmptyAdditionalMimeType);
+    }
 
-        // If the MIME type is text, offer it in some other common formats.
-        if let Some(text_data_path) = text_data_path {
-            let text_mimes = ["text/plain;charset=utf-8",
-                              "text/plain",
-                              "STRING",
-                              "UTF8_STRING",
-                              "TEXT"];
-            for &mime_type in &text_mimes {
-                // We don't want to overwrite an explicit mime type, because it might be bound to a
-                // different data_path
-                if !data_paths.contains_key(mime_type) {
-                    data_paths.insert(mime_type.to_string(), text_data_path.clone());
-                }
-            }
-        }
-        data_paths
-    };
+    Ok(())
+}
+
+// Copies the source data and sets the data sources as the selection.
+fn select(common: &mut CommonData,
+          options: Options,
+          sources: Vec<MimeSource>)
+          -> Result<Selection, Error> {
+    let primary = options.clipboard != ClipboardType::Regular;
+    let devices = get_devices(common, primary, options.seat.clone())?;
+    set_selections(common, options, vec![(devices, sources)])
+}
+
+// Copies the source data of each target and sets it as the selection of the target's devices.
+fn set_selections(common: &mut CommonData,
+                  options: Options,
+                  targets: Vec<(Vec<ZwlrDataControlDeviceV1>, Vec<MimeSource>)>)
+                  -> Result<Selection, Error> {
+    // The standard input can be streamed only to a single paste request, and only once.
+    let mut stream_stdin =
+        options.stream_stdin && options.serve_requests == ServeRequests::Only(1);
 
     // This bool can be set to true when serving a request: either if an error occurs, or if the
     // number of requests to serve was limited and the last request was served.
     let should_quit = Rc::new(Cell::new(false));
     // An error that occurred while serving a request, if any.
     let error = Rc::new(RefCell::new(None::<DataSourceError>));
-    let serve_requests = Rc::new(Cell::new(serve_requests));
+    let serve_requests = Rc::new(Cell::new(options.serve_requests));
+    // When the selection was set or the last request was served.
+    let last_request = Rc::new(Cell::new(Instant::now()));
+    // The sources which haven't been replaced or destroyed yet, for telling when the selection is
+    // lost.
+    let live_sources = Rc::new(Cell::new(0));
+    let owned = Arc::new(AtomicBool::new(true));
+
+    // The temp files are removed when the selection is dropped, so build it up as we go.
+    let mut selection = Selection { should_quit: should_quit.clone(),
+                                    sources: Vec::new(),
+                                    data_sources: Vec::new(),
+                                    error: error.clone(),
+                                    keep_temp_files: options.keep_temp_files,
+                                    expires_at: None,
+                                    idle_timeout: options.idle_timeout,
+                                    last_request: last_request.clone(),
+                                    owned: owned.clone() };
+
+    // For ClipboardType::Both each device needs to appear twice because separate data sources need
+    // to be made for the regular and the primary clipboards (data sources cannot be reused).
+    let primary_flags: &[bool] = match options.clipboard {
+        ClipboardType::Regular => &[false],
+        ClipboardType::Primary => &[true],
+        ClipboardType::Both => &[false, true],
+    };
 
-    // Create an iterator over (device, primary) for source creation later.
-    //
-    // This is needed because for ClipboardType::Both each device needs to appear twice because
-    // separate data sources need to be made for the regular and the primary clipboards (data
-    // sources cannot be reused).
-    let devices_iter = devices.iter().flat_map(|device| {
-                                         let first = match clipboard {
-                                             ClipboardType::Regular => iter::once((device, false)),
-                                             ClipboardType::Primary => iter::once((device, true)),
-                                             ClipboardType::Both => iter::once((device, false)),
-                                         };
-
-                                         let second = if clipboard == ClipboardType::Both {
-                                             iter::once(Some((device, true)))
-                                         } else {
-                                             iter::once(None)
-                                         };
-
-                                         first.chain(second.filter_map(|x| x))
-                                     });
-
-    // Create the data sources and set them as selections.
-    let sources = devices_iter.map(|(device, primary)| {
-                                  let handler = DataSourceHandler::new(data_paths.clone(),
-                                                                       should_quit.clone(),
-                                                                       serve_requests.clone());
-                                  let data_source = clipboard_manager.create_data_source(|source| {
-                                                        source.implement(handler, error.clone())
-                                                    })
-                                                    .unwrap();
-
-                                  for mime_type in data_paths.keys() {
-                                      data_source.offer(mime_type.clone());
-                                  }
-
-                                  if primary {
-                                      device.set_primary_selection(Some(&data_source));
-                                  } else {
-                                      device.set_selection(Some(&data_source));
-                                  }
-
-                                  // If we need to serve 0 requests, kill the data source right away.
-                                  if let ServeRequests::Only(0) = serve_requests.get() {
-                                      data_source.destroy();
-                                  }
-
-                                  data_source.into()
-                              })
-                              .collect::<Vec<Proxy<_>>>();
+    for (devices, sources) in targets {
+        // Collect the source data to copy, and the MIME types to offer in order.
+        let (data_sources, offered_mime_types) =
+            make_data_sources(&options, sources, &mut stream_stdin)?;
+        selection.data_sources.push(data_sources.clone());
+
+        let devices_iter = devices.iter().flat_map(|device| {
+                                             primary_flags.iter()
+                                                          .map(move |&primary| (device, primary))
+                                         });
+
+        // Create the data sources and set them as selections.
+        for (device, primary) in devices_iter {
+            let handler = DataSourceHandler::new(data_sources.clone(),
+                                                 should_quit.clone(),
+                                                 serve_requests.clone(),
+                                                 last_request.clone(),
+                                                 live_sources.clone(),
+                                                 owned.clone());
+            let data_source =
+                common.clipboard_manager
+                      .create_data_source(|source| source.implement(handler, error.clone()))
+                      .unwrap();
+
+            for mime_type in &offered_mime_types {
+                data_source.offer(mime_type.clone());
+            }
 
-    Ok(PreparedCopy { should_quit,
-                      queue,
-                      sources,
-                      data_paths,
-                      error })
+            if primary {
+                device.set_primary_selection(Some(&data_source));
+            } else {
+                device.set_selection(Some(&data_source));
+            }
+
+            // If we need to serve 0 requests, kill the data source right away. It never counts as
+            // live then.
+            if let ServeRequests::Only(0) = serve_requests.get() {
+                data_source.destroy();
+            } else {
+                live_sources.set(live_sources.get() + 1);
+            }
+
+            selection.sources.push(data_source.into());
+        }
+    }
+
+    // If all sources were destroyed right away, none of them stays the selection.
+    if live_sources.get() == 0 {
+        owned.store(false, Ordering::SeqCst);
+    }
+
+    // Make sure the compositor has received the selection before returning. Otherwise someone
+    // pasting right after wl-copy returns could still get the old contents.
+    common.queue
+          .sync_roundtrip()
+          .map_err(Error::WaylandCommunication)?;
+
+    // The selection is set now, so start counting down.
+    selection.expires_at = options.expire_after.map(|x| Instant::now() + x);
+    last_request.set(Instant::now());
+
+    Ok(selection)
+}
+
+// Copies the source data, returning the data sources and the MIME types to offer in order.
+fn make_data_sources(options: &Options,
+                     sources: Vec<MimeSource>,
+                     stream_stdin: &mut bool)
+                     -> Result<(DataSources, Vec<String>), Error> {
+    let mut data_sources = HashMap::new();
+    let mut offered_mime_types = Vec::new();
+    let mut text_data_source = None;
+    for MimeSource { source, mime_type } in sources.into_iter() {
+        let mime_type = match mime_type {
+            MimeType::Specific(x) => MimeType::Specific(normalize_mime_type(&x)),
+            x => x,
+        };
+
+        let is_stdin = matches!(source, Source::StdIn);
+        let result = make_source(source,
+                                 mime_type,
+                                 options.trim_newline,
+                                 options.strip_ansi,
+                                 &options.text_mime_types,
+                                 *stream_stdin,
+                                 options.temp_dir.as_deref());
+        if is_stdin {
+            *stream_stdin = false;
+        }
+
+        let (mime_type, data_source) = match result {
+            Ok(x) => x,
+            Err(err) => {
+                // Don't leave behind the temp files of the sources created so far.
+                drop(remove_temp_files(&mut data_sources));
+                return Err(Error::TempCopy(err));
+            }
+        };
+
+        let mime_type_is_text = is_text_or_listed(&mime_type, &options.text_mime_types);
+        let mime_type_is_html = is_html(&mime_type);
+
+        match data_sources.entry(mime_type) {
+            Entry::Occupied(_) => {
+                // This MIME type has already been specified, so ignore it.
+                if let SourceData::File(mut data_path) = data_source {
+                    if let Err(err) = remove_temp_file(&mut data_path) {
+                        drop(remove_temp_files(&mut data_sources));
+                        return Err(err);
+                    }
+                }
+            }
+            Entry::Vacant(entry) => {
+                let data_source = Rc::new(RefCell::new(data_source));
+
+                if text_data_source.is_none() && mime_type_is_text {
+                    text_data_source = Some((data_source.clone(), mime_type_is_html));
+                }
+
+                offered_mime_types.push(entry.key().clone());
+                entry.insert(data_source);
+            }
+        }
+    }
+
+    // Offer the data of the first source under the additional MIME types.
+    let first_data_source = offered_mime_types.first().map(|x| data_sources[x].clone());
+    if let Some(first_data_source) = first_data_source {
+        for mime_type in &options.additional_mime_types {
+            if let Entry::Vacant(entry) = data_sources.entry(normalize_mime_type(mime_type)) {
+                offered_mime_types.push(entry.key().clone());
+                entry.insert(first_data_source.clone());
+            }
+        }
+    }
+
+    // If the MIME type is text, offer it in some other common formats, unless asked not to.
+    let text_data_source =
+        text_data_source.filter(|_| !options.omit_additional_text_mime_types);
+    if let Some((text_data_source, text_is_html)) = text_data_source {
+        // Apps asking for plain text want the text of HTML rather than the markup.
+        let text_data_source = if text_is_html {
+            match make_plain_text_source(&text_data_source.borrow()) {
+                Ok(data_source) => Rc::new(RefCell::new(data_source)),
+                Err(err) => {
+                    drop(remove_temp_files(&mut data_sources));
+                    return Err(Error::TempCopy(err));
+                }
+            }
+        } else {
+            text_data_source
+        };
+
+        let text_mimes = ["text/plain;charset=utf-8",
+                          "text/plain",
+                          "STRING",
+                          "UTF8_STRING",
+                          "TEXT"];
+        let mut text_mime_types = Vec::new();
+        for &mime_type in &text_mimes {
+            // We don't want to overwrite an explicit mime type, because it might be bound to a
+            // different data source.
+            if !data_sources.contains_key(mime_type) {
+                data_sources.insert(mime_type.to_string(), text_data_source.clone());
+                text_mime_types.push(mime_type.to_string());
+            }
+        }
+
+        if options.additional_text_mime_types_first {
+            offered_mime_types.splice(0..0, text_mime_types);
+        } else {
+            offered_mime_types.extend(text_mime_types);
+        }
+    }
+    Ok((data_sources, offered_mime_types))
 }
 
 /// Copies data to the clipboard.
@@ -840,6 +1774,10 @@ fn prepare_copy_internal(options: Options,
 /// The data is copied from `source` and offered in the `mime_type` MIME type. See `Options` for
 /// customizing the behavior of this operation.
 ///
+/// The new contents replace the previous ones in a single step, so there's no need to clear the
+/// clipboard beforehand. Clearing it first leaves the clipboard empty for a moment, which paste
+/// UIs and clipboard managers can observe.
+///
 /// # Examples
 ///
 /// ```no_run
@@ -867,7 +1805,7 @@ pub fn copy(options: Options, source: Source, mime_type: MimeType) -> Result<(),
 ///
 /// If multiple sources specify the same MIME type, the first one is offered. If one of the MIME
 /// types is text, all automatically added plain text offers will fall back to the first source
-/// with a text MIME type.
+/// with a text MIME type. If that source is HTML, they offer a plain text version of it instead.
 ///
 /// # Examples
 ///
@@ -890,18 +1828,94 @@ pub fn copy_multi(options: Options, sources: Vec<MimeSource>) -> Result<(), Erro
     copy_internal(options, sources, None)
 }
 
+/// Copies data to the clipboard using an existing connection.
+///
+/// This is the same as `copy()`, except that `connection` is used instead of connecting to the
+/// compositor anew. The selection is given up when this function returns, so the connection can
+/// be used for further operations.
+///
+/// Returns `Error::ForegroundRequired` if `foreground` is `false`, since the connection can't be
+/// moved into a separate thread.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate wl_clipboard_rs;
+/// # extern crate failure;
+/// # use failure::Error;
+/// # fn foo() -> Result<(), Error> {
+/// use wl_clipboard_rs::{connection::Connection,
+///                       copy::{copy_with_connection, MimeType, Options, Source, ServeRequests}};
+///
+/// let mut connection = Connection::new()?;
+/// for i in 0..10 {
+///     let mut opts = Options::new();
+///     opts.foreground(true)
+///         .serve_requests(ServeRequests::Only(1));
+///     copy_with_connection(&mut connection,
+///                          opts,
+///                          Source::Bytes(vec![i].into()),
+///                          MimeType::Autodetect)?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[inline]
+pub fn copy_with_connection(connection: &mut Connection,
+                            options: Options,
+                            source: Source,
+                            mime_type: MimeType)
+                            -> Result<(), Error> {
+    let sources = vec![MimeSource { source, mime_type }];
+    copy_multi_with_connection(connection, options, sources)
+}
+
+/// Copies data to the clipboard, offering multiple data sources, using an existing connection.
+///
+/// This is the same as `copy_multi()`, except that `connection` is used instead of connecting to
+/// the compositor anew. The selection is given up when this function returns, so the connection
+/// can be used for further operations.
+///
+/// Returns `Error::ForegroundRequired` if `foreground` is `false`, since the connection can't be
+/// moved into a separate thread.
+pub fn copy_multi_with_connection(connection: &mut Connection,
+                                  options: Options,
+                                  sources: Vec<MimeSource>)
+                                  -> Result<(), Error> {
+    if !options.foreground {
+        return Err(Error::ForegroundRequired);
+    }
+
+    check_options(&options)?;
+    check_version(&connection.common,
+                  options.clipboard != ClipboardType::Regular)?;
+
+    let selection = select(&mut connection.common, options, sources)?;
+    let CommonData { display, queue, .. } = &mut connection.common;
+    selection.serve(queue, display, None)
+}
+
 pub(crate) fn copy_internal(options: Options,
                             sources: Vec<MimeSource>,
                             socket_name: Option<OsString>)
                             -> Result<(), Error> {
-    if options.foreground {
-        prepare_copy_internal(options, sources, socket_name)?.serve()
+    let foreground = options.foreground;
+    run_copy(foreground,
+             move || prepare_copy_internal(options, sources, socket_name))
+}
+
+// Prepares the copy and serves the requests, on a separate thread unless `foreground` is set.
+fn run_copy<F>(foreground: bool, prepare: F) -> Result<(), Error>
+    where F: FnOnce() -> Result<PreparedCopy, Error> + Send + 'static
+{
+    if foreground {
+        prepare()?.serve()
     } else {
         // The copy must be prepared on the thread because PreparedCopy isn't Send.
         // To receive errors from prepare_copy, use a channel.
         let (tx, rx) = sync_channel(1);
 
-        thread::spawn(move || match prepare_copy_internal(options, sources, socket_name) {
+        thread::spawn(move || match prepare() {
                           Ok(prepared_copy) => {
                               // prepare_copy completed successfully, report that.
                               drop(tx.send(None));
@@ -919,3 +1933,245 @@ pub(crate) fn copy_internal(options: Options,
         Ok(())
     }
 }
+
+/// Copies different data to the clipboards of different seats.
+///
+/// The data for each seat in `sources` is copied from the source and offered in the MIME type.
+/// `Seat::All` stands for all seats not listed separately. The `seat` option is ignored, the other
+/// options apply to every seat.
+///
+/// Returns `Error::DuplicateSeat` if two of the seats turn out to be the same one, for example
+/// when it's given both by its name and by its index.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate wl_clipboard_rs;
+/// # use wl_clipboard_rs::copy::Error;
+/// # fn foo() -> Result<(), Error> {
+/// use std::collections::HashMap;
+/// use wl_clipboard_rs::copy::{copy_to_seats, MimeType, Options, Seat, Source};
+///
+/// let mut sources = HashMap::new();
+/// sources.insert(Seat::Specific("seat0".to_string()),
+///                (MimeType::Text, Source::Bytes(b"first"[..].into())));
+/// sources.insert(Seat::All, (MimeType::Text, Source::Bytes(b"others"[..].into())));
+/// copy_to_seats(Options::new(), sources)?;
+/// # Ok(())
+/// # }
+/// ```
+#[inline]
+pub fn copy_to_seats(options: Options,
+                     sources: HashMap<Seat, (MimeType, Source)>)
+                     -> Result<(), Error> {
+    copy_to_seats_internal(options, sources, None)
+}
+
+pub(crate) fn copy_to_seats_internal(options: Options,
+                                     sources: HashMap<Seat, (MimeType, Source)>,
+                                     socket_name: Option<OsString>)
+                                     -> Result<(), Error> {
+    let foreground = options.foreground;
+    run_copy(foreground, move || {
+        check_options(&options)?;
+
+        let primary = options.clipboard != ClipboardType::Regular;
+        let mut common = initialize(primary, socket_name)?;
+        create_devices(&mut common, primary)?;
+
+        let mut targets = Vec::new();
+        let mut other_seats_source = None;
+        let mut listed_devices = Vec::<ZwlrDataControlDeviceV1>::new();
+        for (seat, (mime_type, source)) in sources {
+            let source = MimeSource { source, mime_type };
+            if let Seat::All = seat {
+                other_seats_source = Some(source);
+                continue;
+            }
+
+            let devices = find_devices(&common, &seat)?;
+            if devices.is_empty() {
+                return Err(Error::SeatNotFound);
+            }
+
+            // Otherwise which data ends up on the seat would depend on the HashMap order.
+            let listed = |device: &ZwlrDataControlDeviceV1| {
+                listed_devices.iter().any(|x| x.as_ref().equals(device.as_ref()))
+            };
+            if devices.iter().any(listed) {
+                return Err(Error::DuplicateSeat);
+            }
+
+            listed_devices.extend(devices.iter().cloned());
+            targets.push((devices, vec![source]));
+        }
+
+        if let Some(source) = other_seats_source {
+            let devices = find_devices(&common, &Seat::All)?
+                          .into_iter()
+                          .filter(|device| {
+                              !listed_devices.iter()
+                                             .any(|x| x.as_ref().equals(device.as_ref()))
+                          })
+                          .collect::<Vec<_>>();
+            if !devices.is_empty() {
+                targets.push((devices, vec![source]));
+            }
+        }
+
+        let selection = set_selections(&mut common, options, targets)?;
+
+        Ok(PreparedCopy { display: common.display,
+                          queue: common.queue,
+                          selection })
+    })
+}
+
+/// Copies data to the clipboard, serving requests on a separate thread until cancelled.
+///
+/// This is the same as `copy()` with `foreground` set to `false`, except that the returned guard
+/// keeps control over the selection: dropping it or calling `CopyGuard::cancel()` gives up the
+/// selection and removes the temporary files. The `foreground` flag is ignored.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate wl_clipboard_rs;
+/// # use wl_clipboard_rs::copy::Error;
+/// # fn foo() -> Result<(), Error> {
+/// use wl_clipboard_rs::copy::{copy_guarded, MimeType, Options, Source};
+///
+/// let guard = copy_guarded(Options::new(), Source::Bytes([1, 2, 3][..].into()),
+///                          MimeType::Autodetect)?;
+///
+/// // Do other work while the data is in the clipboard.
+///
+/// guard.cancel()?;
+/// # Ok(())
+/// # }
+/// ```
+#[inline]
+pub fn copy_guarded(options: Options,
+                    source: Source,
+                    mime_type: MimeType)
+                    -> Result<CopyGuard, Error> {
+    let sources = vec![MimeSource { source, mime_type }];
+    copy_guarded_internal(options, sources, None)
+}
+
+/// Copies data to the clipboard, offering multiple data sources, serving requests on a separate
+/// thread until cancelled.
+///
+/// This is the same as `copy_multi()` with `foreground` set to `false`, except that the returned
+/// guard keeps control over the selection: dropping it or calling `CopyGuard::cancel()` gives up
+/// the selection and removes the temporary files. The `foreground` flag is ignored.
+#[inline]
+pub fn copy_multi_guarded(options: Options, sources: Vec<MimeSource>) -> Result<CopyGuard, Error> {
+    copy_guarded_internal(options, sources, None)
+}
+
+pub(crate) fn copy_guarded_internal(options: Options,
+                                    sources: Vec<MimeSource>,
+                                    socket_name: Option<OsString>)
+                                    -> Result<CopyGuard, Error> {
+    let (cancel_read, cancel_write) = pipe().map_err(Error::PipeCreation)?;
+
+    // The copy must be prepared on the thread because PreparedCopy isn't Send.
+    // To receive errors from prepare_copy, use a channel.
+    let (tx, rx) = sync_channel(1);
+
+    let thread = thread::spawn(move || match prepare_copy_internal(options, sources, socket_name) {
+                                   Ok(prepared_copy) => {
+                                       // prepare_copy completed successfully, report that.
+                                       let owned = prepared_copy.selection.owned.clone();
+                                       drop(tx.send(Ok(owned.clone())));
+
+                                       let result = prepared_copy.serve_until_cancelled(
+                                           cancel_read.as_raw_fd(),
+                                       );
+                                       owned.store(false, Ordering::SeqCst);
+                                       result
+                                   }
+                                   Err(err) => {
+                                       drop(tx.send(Err(err)));
+                                       Ok(())
+                                   }
+                               });
+
+    let owned = match rx.recv().unwrap() {
+        Ok(owned) => owned,
+        Err(err) => {
+            drop(thread.join());
+            return Err(err);
+        }
+    };
+
+    Ok(CopyGuard { cancel: Some(cancel_write),
+                   thread: Some(thread),
+                   owned })
+}
+
+/// Computes the MIME types a copy would offer, without copying.
+///
+/// The sources are read and the MIME types inferred the same way as for `copy_multi()`, but no
+/// connection to the compositor is made. Any temporary files are removed before returning.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate wl_clipboard_rs;
+/// # use wl_clipboard_rs::copy::Error;
+/// # fn foo() -> Result<(), Error> {
+/// use wl_clipboard_rs::copy::{dry_run, MimeSource, MimeType, Options, Source};
+///
+/// let sources = vec![MimeSource { source: Source::Bytes(b"hello"[..].into()),
+///                                 mime_type: MimeType::Text }];
+/// for offer in dry_run(&Options::new(), sources)? {
+///     println!("{} from data {}", offer.mime_type, offer.data);
+/// }
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+pub fn dry_run(options: &Options, sources: Vec<MimeSource>) -> Result<Vec<DryRunOffer>, Error> {
+    check_options(options)?;
+
+    let mut stream_stdin =
+        options.stream_stdin && options.serve_requests == ServeRequests::Only(1);
+    let (mut data_sources, offered_mime_types) =
+        make_data_sources(options, sources, &mut stream_stdin)?;
+
+    // Number the data in the order it's first offered.
+    let mut data = Vec::<Rc<RefCell<SourceData>>>::new();
+    let mut offers = Vec::with_capacity(offered_mime_types.len());
+    for mime_type in offered_mime_types {
+        let data_source = data_sources[&mime_type].clone();
+        let index = match data.iter().position(|x| Rc::ptr_eq(x, &data_source)) {
+            Some(index) => index,
+            None => {
+                data.push(data_source.clone());
+                data.len() - 1
+            }
+        };
+
+        let size = match &*data_source.borrow() {
+            SourceData::File(path) => fs::metadata(path).map(|metadata| Some(metadata.len())),
+            SourceData::Bytes(bytes) => Ok(Some(bytes.len() as u64)),
+            SourceData::StdIn => Ok(None),
+        };
+        let size = match size {
+            Ok(size) => size,
+            Err(err) => {
+                drop(remove_temp_files(&mut data_sources));
+                return Err(Error::TempCopy(SourceCreationError::TempFileMetadata(err)));
+            }
+        };
+
+        offers.push(DryRunOffer { mime_type,
+                                  data: index,
+                                  size });
+    }
+
+    remove_temp_files(&mut data_sources)?;
+    Ok(offers)
+}
@@ -1,16 +1,26 @@
 use std::{
     cell::{Cell, RefCell},
-    collections::{HashMap, HashSet},
+    collections::HashMap,
+    ffi::CString,
     fs::File,
     io,
     os::unix::io::{IntoRawFd, RawFd},
-    path::PathBuf,
     rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
 };
 
 use derive_new::new;
 use failure::Fail;
-use nix::unistd::close;
+use log::info;
+use nix::{
+    errno::Errno,
+    sys::memfd::{memfd_create, MemFdCreateFlag},
+    unistd::{close, lseek, write, Whence},
+};
 use wayland_client::{
     protocol::{wl_seat::WlSeat, *},
     NewProxy,
@@ -22,7 +32,7 @@ use wayland_protocols::wlr::unstable::data_control::v1::client::{
 };
 
 use crate::{
-    copy::ServeRequests,
+    copy::{ServeRequests, SourceData},
     seat_data::SeatData,
     utils::{self, copy_data},
 };
@@ -55,8 +65,8 @@ impl zwlr_data_control_device_v1::EventHandler for DataDeviceHandler {
     fn data_offer(&mut self,
                   _device: ZwlrDataControlDeviceV1,
                   offer: NewProxy<ZwlrDataControlOfferV1>) {
-        // Make a container for the new offer's mime types.
-        let mime_types = RefCell::new(HashSet::<String>::with_capacity(1));
+        // Make a container for the new offer's mime types, kept in the order they're offered.
+        let mime_types = RefCell::new(Vec::<String>::with_capacity(1));
 
         // Bind the new offer with a handler that fills out mime types.
         offer.implement(DataControlOfferHandler, mime_types);
@@ -67,6 +77,9 @@ impl zwlr_data_control_device_v1::EventHandler for DataDeviceHandler {
                  offer: Option<ZwlrDataControlOfferV1>) {
         if !self.primary {
             self.selection(offer);
+        } else if let Some(offer) = offer {
+            // We're not interested in this offer.
+            offer.destroy();
         }
     }
 
@@ -77,6 +90,9 @@ impl zwlr_data_control_device_v1::EventHandler for DataDeviceHandler {
 
         if self.primary {
             self.selection(offer);
+        } else if let Some(offer) = offer {
+            // We're not interested in this offer.
+            offer.destroy();
         }
     }
 
@@ -91,8 +107,11 @@ pub struct DataControlOfferHandler;
 
 impl zwlr_data_control_offer_v1::EventHandler for DataControlOfferHandler {
     fn offer(&mut self, offer: ZwlrDataControlOfferV1, mime_type: String) {
-        let mime_types = offer.as_ref().user_data::<RefCell<HashSet<_>>>().unwrap();
-        mime_types.borrow_mut().insert(mime_type);
+        let mime_types = offer.as_ref().user_data::<RefCell<Vec<_>>>().unwrap();
+        let mut mime_types = mime_types.borrow_mut();
+        if !mime_types.contains(&mime_type) {
+            mime_types.push(mime_type);
+        }
     }
 }
 
@@ -103,13 +122,76 @@ pub enum DataSourceError {
 
     #[fail(display = "Couldn't copy the data to the target file descriptor")]
     Copy(#[cause] utils::CopyDataError),
+
+    #[fail(display = "Couldn't put the data into an in-memory file")]
+    Buffer(#[cause] nix::Error),
+}
+
+/// Writes all of `data` into `fd`, retrying on short writes.
+fn write_all(fd: RawFd, mut data: &[u8]) -> Result<(), nix::Error> {
+    while !data.is_empty() {
+        match write(fd, data) {
+            Ok(written) => data = &data[written..],
+            Err(nix::Error::Sys(Errno::EINTR)) => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies `data` into `fd` without blocking.
+///
+/// The data is put into an in-memory file and copied with `copy_data()`, just like the data in
+/// temporary files. Writing it right away would block serving until the pasting client reads all
+/// of it, or forever if it never does.
+///
+/// Like `copy_data()`, this function assumes ownership of `fd`, that is, it closes it by itself.
+fn write_data(fd: RawFd, data: &[u8]) -> Result<(), DataSourceError> {
+    // Don't leak it into the other child processes, copy_data() dups it for its own.
+    let name = CString::new("wl-clipboard-rs").unwrap();
+    let buffer = match memfd_create(&name, MemFdCreateFlag::MFD_CLOEXEC) {
+        Ok(buffer) => buffer,
+        Err(err) => {
+            let _ = close(fd);
+            return Err(DataSourceError::Buffer(err));
+        }
+    };
+
+    if let Err(err) = write_all(buffer, data).and_then(|()| lseek(buffer, 0, Whence::SeekSet)) {
+        let _ = close(buffer);
+        let _ = close(fd);
+        return Err(DataSourceError::Buffer(err));
+    }
+
+    copy_data(Some(buffer), fd, false).map_err(DataSourceError::Copy)
 }
 
 #[derive(new)]
 pub struct DataSourceHandler {
-    data_paths: HashMap<String, Rc<RefCell<PathBuf>>>,
+    data_sources: HashMap<String, Rc<RefCell<SourceData>>>,
     should_quit: Rc<Cell<bool>>,
     serve_requests: Rc<Cell<ServeRequests>>,
+    last_request: Rc<Cell<Instant>>,
+    // The number of our sources which haven't been replaced by some other application or
+    // destroyed.
+    live_sources: Rc<Cell<usize>>,
+    // Cleared once all of our sources have been replaced.
+    owned: Arc<AtomicBool>,
+}
+
+impl DataSourceHandler {
+    // Destroys the source, clearing the ownership once none of our sources are left.
+    fn destroy(&self, source: &ZwlrDataControlSourceV1) {
+        source.destroy();
+
+        // With several clipboards the selection is lost only once all of them are gone.
+        let live_sources = self.live_sources.get().saturating_sub(1);
+        self.live_sources.set(live_sources);
+        if live_sources == 0 {
+            self.owned.store(false, Ordering::SeqCst);
+        }
+    }
 }
 
 impl zwlr_data_control_source_v1::EventHandler for DataSourceHandler {
@@ -117,24 +199,30 @@ impl zwlr_data_control_source_v1::EventHandler for DataSourceHandler {
         // Check if some other source already handled a paste request and indicated that we should
         // quit.
         if self.should_quit.get() {
-            source.destroy();
+            self.destroy(&source);
             return;
         }
 
+        info!("Paste request for MIME type: {}", mime_type);
+
         // I'm not sure if it's the compositor's responsibility to check that the mime type is
         // valid. Let's check here just in case.
-        if !&self.data_paths.contains_key(&mime_type) {
+        if !&self.data_sources.contains_key(&mime_type) {
             let _ = close(target_fd);
             return;
         }
 
-        let data_path = &self.data_paths[&mime_type];
-
-        let file = File::open(&*data_path.borrow()).map_err(DataSourceError::FileOpen);
-        let result = file.and_then(|data_file| {
-                         let data_fd = data_file.into_raw_fd();
-                         copy_data(Some(data_fd), target_fd, false).map_err(DataSourceError::Copy)
-                     });
+        let result = match &*self.data_sources[&mime_type].borrow() {
+            SourceData::File(data_path) => {
+                let file = File::open(data_path).map_err(DataSourceError::FileOpen);
+                file.and_then(|data_file| {
+                        let data_fd = data_file.into_raw_fd();
+                        copy_data(Some(data_fd), target_fd, false).map_err(DataSourceError::Copy)
+                    })
+            }
+            SourceData::Bytes(data) => write_data(target_fd, data),
+            SourceData::StdIn => copy_data(None, target_fd, false).map_err(DataSourceError::Copy),
+        };
 
         let mut error = source.as_ref()
                               .user_data::<Rc<RefCell<Option<DataSourceError>>>>()
@@ -144,6 +232,9 @@ impl zwlr_data_control_source_v1::EventHandler for DataSourceHandler {
             *error = Some(err);
         }
 
+        // Restart the idle timeout.
+        self.last_request.set(Instant::now());
+
         let done = if let ServeRequests::Only(left) = self.serve_requests.get() {
             let left = left.checked_sub(1).unwrap();
             self.serve_requests.set(ServeRequests::Only(left));
@@ -154,11 +245,11 @@ impl zwlr_data_control_source_v1::EventHandler for DataSourceHandler {
 
         if done || error.is_some() {
             self.should_quit.set(true);
-            source.destroy();
+            self.destroy(&source);
         }
     }
 
     fn cancelled(&mut self, source: ZwlrDataControlSourceV1) {
-        source.destroy();
+        self.destroy(&source);
     }
 }
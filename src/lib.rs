@@ -121,6 +121,7 @@ mod seat_data;
 #[allow(unsafe_code)] // It's more convenient for testing some stuff.
 mod tests;
 
+pub mod connection;
 pub mod copy;
 pub mod paste;
 pub mod utils;
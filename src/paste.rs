@@ -1,24 +1,25 @@
 //! Getting the offered MIME types and the clipboard contents.
 
 use std::{
-    cell::{Cell, RefCell},
+    cell::RefCell,
     collections::HashSet,
     ffi::OsString,
-    io, mem,
+    io::{self, Read},
+    mem,
     os::unix::io::AsRawFd,
-    rc::Rc,
 };
 
 use failure::Fail;
+use log::info;
 use os_pipe::{pipe, PipeReader};
-use wayland_client::{ConnectError, EventQueue};
+use wayland_client::{protocol::wl_seat::WlSeat, ConnectError, EventQueue};
 use wayland_protocols::wlr::unstable::data_control::v1::client::zwlr_data_control_offer_v1::ZwlrDataControlOfferV1;
 
 use crate::{
-    common::{self, initialize, CommonData},
-    handlers::DataDeviceHandler,
+    common::{self, check_version, create_devices, find_seat, initialize, CommonData},
+    connection::Connection,
     seat_data::SeatData,
-    utils::is_text,
+    utils::{is_text, normalize_mime_type},
 };
 
 /// The clipboard to operate on.
@@ -45,14 +46,15 @@ impl Default for ClipboardType {
 pub enum MimeType<'a> {
     /// Request any available MIME type.
     ///
-    /// If multiple MIME types are offered, the requested MIME type is unspecified and depends on
-    /// the order they are received from the Wayland compositor. However, plain text formats are
-    /// prioritized, so if a plain text format is available among others then it will be requested.
+    /// If multiple MIME types are offered, the first one in the order they are received from the
+    /// Wayland compositor is requested. However, plain text formats are prioritized, so if a plain
+    /// text format is available among others then it will be requested.
     Any,
     /// Request a plain text MIME type.
     ///
     /// This will request one of the multiple common plain text MIME types. It will prioritize MIME
-    /// types known to return UTF-8 text.
+    /// types known to return UTF-8 text: `text/plain;charset=utf-8` comes first, then
+    /// `UTF8_STRING`, then `text/plain` in any encoding, then `STRING` and `TEXT`.
     Text,
     /// Request the given MIME type, and if it's not available fall back to `MimeType::Text`.
     ///
@@ -61,6 +63,12 @@ pub enum MimeType<'a> {
     TextWithPriority(&'a str),
     /// Request a specific MIME type.
     Specific(&'a str),
+    /// Request the first offered MIME type matching the given pattern.
+    ///
+    /// In the pattern, `*` matches any sequence of characters, for example `image/*` matches all
+    /// image MIME types and `*` matches any MIME type. The matching is case-insensitive. The MIME
+    /// types are tried in the order they are offered.
+    Pattern(&'a str),
 }
 
 /// Seat to operate on.
@@ -71,6 +79,11 @@ pub enum Seat<'a> {
     /// This is perfectly fine when only a single seat is present, so for most configurations.
     Unspecified,
     /// Operate on a seat with the given name.
+    ///
+    /// If no seat has this name and the name is a number, the seat with this index (counting from
+    /// 0 in the order the compositor advertised the seats, as listed by
+    /// `utils::get_seat_names()`) is used instead. Seats of version 1 count towards the index,
+    /// but picking one fails with `Error::SeatVersionUnsupported`.
     Specific(&'a str),
 }
 
@@ -107,14 +120,23 @@ pub enum Error {
            name, version)]
     MissingProtocol { name: &'static str, version: u32 },
 
+    #[fail(display = "The {} global was removed before it could be bound", name)]
+    GlobalBindFailed { name: &'static str },
+
     #[fail(display = "The compositor does not support primary selection")]
     PrimarySelectionUnsupported,
 
     #[fail(display = "The requested seat was not found")]
     SeatNotFound,
 
+    #[fail(display = "The requested seat has version 1, which is not supported")]
+    SeatVersionUnsupported,
+
     #[fail(display = "Couldn't create a pipe for content transfer")]
     PipeCreation(#[cause] io::Error),
+
+    #[fail(display = "Couldn't read the clipboard contents")]
+    ContentsRead(#[cause] io::Error),
 }
 
 impl From<common::Error> for Error {
@@ -125,78 +147,391 @@ impl From<common::Error> for Error {
             WaylandConnection(err) => Error::WaylandConnection(err),
             WaylandCommunication(err) => Error::WaylandCommunication(err),
             MissingProtocol { name, version } => Error::MissingProtocol { name, version },
+            GlobalBindFailed { name } => Error::GlobalBindFailed { name },
         }
     }
 }
 
-fn get_offer(primary: bool,
-             seat: Seat<'_>,
-             socket_name: Option<OsString>)
-             -> Result<(EventQueue, ZwlrDataControlOfferV1), Error> {
-    let CommonData { mut queue,
-                     clipboard_manager,
-                     seats, } = initialize(primary, socket_name)?;
+impl From<common::DevicesError> for Error {
+    fn from(x: common::DevicesError) -> Self {
+        use common::DevicesError::*;
+
+        match x {
+            NoSeats => Error::NoSeats,
+            WaylandCommunication(err) => Error::WaylandCommunication(err),
+            PrimarySelectionUnsupported => Error::PrimarySelectionUnsupported,
+        }
+    }
+}
+
+impl From<common::SeatError> for Error {
+    fn from(x: common::SeatError) -> Self {
+        use common::SeatError::*;
+
+        match x {
+            NotFound => Error::SeatNotFound,
+            Version1 => Error::SeatVersionUnsupported,
+        }
+    }
+}
+
+// Returns the current offer of the requested seat, or `None` if its clipboard is empty.
+fn find_offer(seats: &[WlSeat],
+              seat_globals: &[u32],
+              seat: Seat<'_>)
+              -> Result<Option<ZwlrDataControlOfferV1>, Error> {
+    // Figure out which offer we're interested in.
+    let seat = match seat {
+        Seat::Unspecified => seats.first().ok_or(Error::SeatNotFound)?,
+        Seat::Specific(name) => find_seat(seats, seat_globals, name)?,
+    };
+
+    let offer = seat.as_ref()
+                    .user_data::<RefCell<SeatData>>()
+                    .unwrap()
+                    .borrow()
+                    .offer
+                    .clone();
+    Ok(offer)
+}
+
+fn current_offer(common: &mut CommonData,
+                 primary: bool,
+                 seat: Seat<'_>)
+                 -> Result<ZwlrDataControlOfferV1, Error> {
+    create_devices(common, primary)?;
+
+    let offer = find_offer(&common.seats.borrow(), &common.seat_globals.borrow(), seat)?;
+    offer.ok_or(Error::ClipboardEmpty)
+}
+
+/// Returns the rank of a plain text MIME type, lower is better, or `None` if it's not text.
+///
+/// UTF-8 is preferred, then plain text in other encodings, then the X11 targets and other text.
+fn text_rank(mime_type: &str) -> Option<u8> {
+    let rank = match &*normalize_mime_type(mime_type) {
+        "text/plain;charset=utf-8" => 0,
+        "UTF8_STRING" => 1,
+        "text/plain" => 2,
+        x if x.starts_with("text/plain;") => 3,
+        "STRING" => 4,
+        "TEXT" => 5,
+        x if is_text(x) => 6,
+        _ => return None,
+    };
+
+    Some(rank)
+}
+
+/// Picks the best plain text MIME type out of the offered ones.
+///
+/// Equally ranked MIME types are ordered alphabetically so that the choice is deterministic.
+pub(crate) fn best_text_mime_type<'a, I>(mime_types: I) -> Option<&'a str>
+    where I: IntoIterator<Item = &'a String>
+{
+    mime_types.into_iter()
+              .filter_map(|x| text_rank(x).map(|rank| (rank, x)))
+              .min()
+              .map(|(_, x)| x.as_str())
+}
+
+/// Checks if the MIME type matches the pattern, where `*` matches any sequence of characters.
+///
+/// The matching is case-insensitive.
+pub(crate) fn matches_pattern(pattern: &str, mime_type: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let mime_type = mime_type.to_ascii_lowercase();
+
+    let mut parts = pattern.split('*');
+    // There's always at least one part.
+    let first = parts.next().unwrap();
+    if !mime_type.starts_with(first) {
+        return false;
+    }
+
+    let mut rest = &mime_type[first.len()..];
+    let mut parts = parts.collect::<Vec<_>>();
+
+    // Without a `*`, the whole MIME type must match.
+    let last = match parts.pop() {
+        Some(last) => last,
+        None => return rest.is_empty(),
+    };
+
+    // Match the parts in between the `*` as early as possible, leaving the most for the rest.
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
 
-    // Check if there are no seats.
-    if seats.borrow_mut().is_empty() {
-        return Err(Error::NoSeats);
+// Picks the MIME type to request out of the offered ones, removing it from the list.
+fn take_mime_type(mime_types: &mut Vec<String>, mime_type: MimeType<'_>) -> Option<String> {
+    fn take(mime_types: &mut Vec<String>, mime_type: &str) -> Option<String> {
+        let index = mime_types.iter().position(|x| x == mime_type)?;
+        Some(mime_types.remove(index))
     }
 
-    let supports_primary = Rc::new(Cell::new(false));
+    let best_text = best_text_mime_type(&*mime_types).map(str::to_string);
 
-    // Go through the seats and get their data devices.
-    for seat in &*seats.borrow_mut() {
-        let handler = DataDeviceHandler::new(seat.clone(), primary, supports_primary.clone());
-        clipboard_manager.get_data_device(seat, |device| device.implement(handler, ()))
-                         .unwrap();
+    match mime_type {
+        MimeType::Any => best_text.or_else(|| mime_types.first().cloned())
+                                  .and_then(|x| take(mime_types, &x)),
+        MimeType::Text => best_text.and_then(|x| take(mime_types, &x)),
+        MimeType::TextWithPriority(priority) => {
+            take(mime_types, priority).or_else(|| best_text.and_then(|x| take(mime_types, &x)))
+        }
+        MimeType::Specific(mime_type) => take(mime_types, mime_type),
+        MimeType::Pattern(pattern) => {
+            let index = mime_types.iter().position(|x| matches_pattern(pattern, x))?;
+            Some(mime_types.remove(index))
+        }
     }
+}
+
+// Starts receiving the offer contents in the given MIME type.
+fn receive(queue: &mut EventQueue,
+           offer: &ZwlrDataControlOfferV1,
+           mime_type: String)
+           -> Result<PipeReader, Error> {
+    info!("Requesting MIME type: {}", mime_type);
+
+    // Create a pipe for content transfer.
+    let (read, write) = pipe().map_err(Error::PipeCreation)?;
 
-    // Retrieve all seat names and offers.
+    // Start the transfer.
+    offer.receive(mime_type, write.as_raw_fd());
+    drop(write);
     queue.sync_roundtrip()
          .map_err(Error::WaylandCommunication)?;
 
-    // Check if the compositor supports primary selection.
-    if primary && !supports_primary.get() {
-        return Err(Error::PrimarySelectionUnsupported);
+    Ok(read)
+}
+
+/// The current clipboard offer.
+///
+/// Unlike `get_contents()`, which picks the MIME type to receive by itself, this allows
+/// inspecting the offered MIME types first and choosing one (or several) to receive. Returned by
+/// `get_offer()`.
+pub struct Offer {
+    common: CommonData,
+    offer: ZwlrDataControlOfferV1,
+    mime_types: Vec<String>,
+}
+
+impl Offer {
+    /// Returns the offered MIME types in the order they were offered.
+    #[inline]
+    pub fn mime_types(&self) -> &[String] {
+        &self.mime_types
     }
 
-    // Figure out which offer we're interested in.
-    let offer = seats.borrow_mut()
-                     .iter()
-                     .map(|seat| {
-                         seat.as_ref()
-                             .user_data::<RefCell<SeatData>>()
-                             .unwrap()
-                             .borrow()
-                     })
-                     .find_map(|data| {
-                         let SeatData { name, offer, .. } = &*data;
-                         match seat {
-                             Seat::Unspecified => return Some(offer.clone()),
-                             Seat::Specific(desired_name) => {
-                                 if let Some(name) = name {
-                                     if name == desired_name {
-                                         return Some(offer.clone());
-                                     }
-                                 }
-                             }
-                         }
-
-                         None
-                     });
-
-    // Check if we found any seat.
-    if offer.is_none() {
-        return Err(Error::SeatNotFound);
+    /// Starts receiving the contents.
+    ///
+    /// The MIME type is picked out of the offered ones according to `mime_type`, like in
+    /// `get_contents()`. Returns the reading end of a pipe containing the contents and the picked
+    /// MIME type.
+    ///
+    /// The contents can be received repeatedly, for example in different MIME types.
+    pub fn receive(&mut self, mime_type: MimeType<'_>) -> Result<(PipeReader, String), Error> {
+        let mime_type = take_mime_type(&mut self.mime_types.clone(), mime_type)
+                        .ok_or(Error::NoMimeType)?;
+
+        let read = receive(&mut self.common.queue, &self.offer, mime_type.clone())?;
+        Ok((read, mime_type))
+    }
+}
+
+/// Retrieves the current clipboard offer.
+///
+/// Returns `Error::ClipboardEmpty` if the clipboard is empty.
+///
+/// If `seat` is `None`, uses an unspecified seat (it depends on the order returned by the
+/// compositor). This is perfectly fine when only a single seat is present, so for most
+/// configurations.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate wl_clipboard_rs;
+/// # use wl_clipboard_rs::paste::Error;
+/// # fn foo() -> Result<(), Error> {
+/// use std::io::Read;
+/// use wl_clipboard_rs::{paste::{get_offer, ClipboardType, MimeType, Seat}};
+///
+/// let mut offer = get_offer(ClipboardType::Regular, Seat::Unspecified)?;
+/// if let Some(mime_type) = offer.mime_types().iter().find(|x| x.starts_with("image/")) {
+///     let mime_type = mime_type.clone();
+///     let (mut pipe, _) = offer.receive(MimeType::Specific(&mime_type))?;
+///
+///     let mut contents = vec![];
+///     pipe.read_to_end(&mut contents).expect("Couldn't read the image");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[inline]
+pub fn get_offer(clipboard: ClipboardType, seat: Seat<'_>) -> Result<Offer, Error> {
+    get_offer_internal(clipboard, seat, None)
+}
+
+// The internal function accepts the socket name, used for tests.
+pub(crate) fn get_offer_internal(clipboard: ClipboardType,
+                                 seat: Seat<'_>,
+                                 socket_name: Option<OsString>)
+                                 -> Result<Offer, Error> {
+    let primary = clipboard == ClipboardType::Primary;
+    let mut common = initialize(primary, socket_name)?;
+    let offer = current_offer(&mut common, primary, seat)?;
+    let mime_types = mem::take(&mut *offer.as_ref()
+                                          .user_data::<RefCell<Vec<String>>>()
+                                          .unwrap()
+                                          .borrow_mut());
+    Ok(Offer { common,
+               offer,
+               mime_types })
+}
+
+/// Retrieves the clipboard contents in several MIME types, joined together.
+///
+/// For each of `mime_types` in order, a MIME type is picked like in `get_contents()` and the
+/// contents in that MIME type are read. The contents are then concatenated in the same order, with
+/// `separator` in between (but not at the end). Returns the concatenated contents and the picked
+/// MIME types.
+///
+/// Returns `Error::NoMimeType` if any of `mime_types` has no matching offered MIME type.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate wl_clipboard_rs;
+/// # use wl_clipboard_rs::paste::Error;
+/// # fn foo() -> Result<(), Error> {
+/// use wl_clipboard_rs::paste::{get_contents_concat, ClipboardType, MimeType, Seat};
+///
+/// let (contents, mime_types) =
+///     get_contents_concat(ClipboardType::Regular,
+///                         Seat::Unspecified,
+///                         &[MimeType::Specific("text/html"), MimeType::Text],
+///                         b"\n")?;
+/// println!("Read {} bytes of {}", contents.len(), mime_types.join(" and "));
+/// # Ok(())
+/// # }
+/// ```
+#[inline]
+pub fn get_contents_concat(clipboard: ClipboardType,
+                           seat: Seat<'_>,
+                           mime_types: &[MimeType<'_>],
+                           separator: &[u8])
+                           -> Result<(Vec<u8>, Vec<String>), Error> {
+    get_contents_concat_internal(clipboard, seat, mime_types, separator, None)
+}
+
+// The internal function accepts the socket name, used for tests.
+pub(crate) fn get_contents_concat_internal(clipboard: ClipboardType,
+                                           seat: Seat<'_>,
+                                           mime_types: &[MimeType<'_>],
+                                           separator: &[u8],
+                                           socket_name: Option<OsString>)
+                                           -> Result<(Vec<u8>, Vec<String>), Error> {
+    let mut offer = get_offer_internal(clipboard, seat, socket_name)?;
+
+    // Check all MIME types up front so as not to read anything in vain.
+    for &mime_type in mime_types {
+        if take_mime_type(&mut offer.mime_types.clone(), mime_type).is_none() {
+            return Err(Error::NoMimeType);
+        }
+    }
+
+    let mut contents = Vec::new();
+    let mut picked_mime_types = Vec::new();
+    for (i, &mime_type) in mime_types.iter().enumerate() {
+        if i > 0 {
+            contents.extend_from_slice(separator);
+        }
+
+        let (mut read, mime_type) = offer.receive(mime_type)?;
+        read.read_to_end(&mut contents).map_err(Error::ContentsRead)?;
+        picked_mime_types.push(mime_type);
     }
 
-    offer.unwrap()
-         .map(|x| (queue, x))
-         .ok_or(Error::ClipboardEmpty)
+    Ok((contents, picked_mime_types))
+}
+
+/// Checks whether the clipboard is empty.
+///
+/// This is cheaper than `get_mime_types()` when only a yes or no answer is needed, for example
+/// for greying out a paste button. A selection which offers no MIME types counts as empty.
+///
+/// If `seat` is `None`, uses an unspecified seat (it depends on the order returned by the
+/// compositor). This is perfectly fine when only a single seat is present, so for most
+/// configurations.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate wl_clipboard_rs;
+/// # use wl_clipboard_rs::paste::Error;
+/// # fn foo() -> Result<(), Error> {
+/// use wl_clipboard_rs::{paste::{is_empty, ClipboardType, Seat}};
+///
+/// if is_empty(ClipboardType::Regular, Seat::Unspecified)? {
+///     println!("Nothing to paste");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[inline]
+pub fn is_empty(clipboard: ClipboardType, seat: Seat<'_>) -> Result<bool, Error> {
+    is_empty_internal(clipboard, seat, None)
+}
+
+/// Checks whether the clipboard is empty using an existing connection.
+///
+/// This is the same as `is_empty()`, except that `connection` is used instead of connecting to
+/// the compositor anew.
+pub fn is_empty_with_connection(connection: &mut Connection,
+                                clipboard: ClipboardType,
+                                seat: Seat<'_>)
+                                -> Result<bool, Error> {
+    let primary = clipboard == ClipboardType::Primary;
+    check_version(&connection.common, primary)?;
+    is_empty_from(&mut connection.common, primary, seat)
+}
+
+// The internal function accepts the socket name, used for tests.
+pub(crate) fn is_empty_internal(clipboard: ClipboardType,
+                                seat: Seat<'_>,
+                                socket_name: Option<OsString>)
+                                -> Result<bool, Error> {
+    let primary = clipboard == ClipboardType::Primary;
+    let mut common = initialize(primary, socket_name)?;
+    is_empty_from(&mut common, primary, seat)
+}
+
+fn is_empty_from(common: &mut CommonData, primary: bool, seat: Seat<'_>) -> Result<bool, Error> {
+    let offer = match current_offer(common, primary, seat) {
+        Ok(offer) => offer,
+        Err(Error::ClipboardEmpty) => return Ok(true),
+        Err(err) => return Err(err),
+    };
+
+    let is_empty = offer.as_ref()
+                        .user_data::<RefCell<Vec<String>>>()
+                        .unwrap()
+                        .borrow()
+                        .is_empty();
+    Ok(is_empty)
 }
 
 /// Retrieves the offered MIME types.
 ///
+/// If the clipboard is empty, returns an empty set rather than `Error::ClipboardEmpty`.
+///
 /// If `seat` is `None`, uses an unspecified seat (it depends on the order returned by the
 /// compositor). This is perfectly fine when only a single seat is present, so for most
 /// configurations.
@@ -221,21 +556,67 @@ pub fn get_mime_types(clipboard: ClipboardType, seat: Seat<'_>) -> Result<HashSe
     get_mime_types_internal(clipboard, seat, None)
 }
 
+/// Retrieves the offered MIME types using an existing connection.
+///
+/// This is the same as `get_mime_types()`, except that `connection` is used instead of connecting
+/// to the compositor anew.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate wl_clipboard_rs;
+/// # extern crate failure;
+/// # use failure::Error;
+/// # fn foo() -> Result<(), Error> {
+/// use wl_clipboard_rs::{connection::Connection,
+///                       paste::{get_mime_types_with_connection, ClipboardType, Seat}};
+///
+/// let mut connection = Connection::new()?;
+/// for _ in 0..10 {
+///     let mime_types = get_mime_types_with_connection(&mut connection,
+///                                                     ClipboardType::Regular,
+///                                                     Seat::Unspecified)?;
+///     println!("{} MIME types", mime_types.len());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn get_mime_types_with_connection(connection: &mut Connection,
+                                      clipboard: ClipboardType,
+                                      seat: Seat<'_>)
+                                      -> Result<HashSet<String>, Error> {
+    let primary = clipboard == ClipboardType::Primary;
+    check_version(&connection.common, primary)?;
+    get_mime_types_from(&mut connection.common, primary, seat)
+}
+
 // The internal function accepts the socket name, used for tests.
 pub(crate) fn get_mime_types_internal(clipboard: ClipboardType,
                                       seat: Seat<'_>,
                                       socket_name: Option<OsString>)
                                       -> Result<HashSet<String>, Error> {
     let primary = clipboard == ClipboardType::Primary;
-    let (_, offer) = get_offer(primary, seat, socket_name)?;
+    let mut common = initialize(primary, socket_name)?;
+    get_mime_types_from(&mut common, primary, seat)
+}
+
+fn get_mime_types_from(common: &mut CommonData,
+                       primary: bool,
+                       seat: Seat<'_>)
+                       -> Result<HashSet<String>, Error> {
+    let offer = match current_offer(common, primary, seat) {
+        Ok(offer) => offer,
+        // An empty clipboard simply doesn't offer any MIME types.
+        Err(Error::ClipboardEmpty) => return Ok(HashSet::new()),
+        Err(err) => return Err(err),
+    };
 
     let mut mime_types = offer.as_ref()
-                              .user_data::<RefCell<HashSet<String>>>()
+                              .user_data::<RefCell<Vec<String>>>()
                               .unwrap()
                               .borrow_mut();
 
-    let empty_hash_set = HashSet::new();
-    Ok(mem::replace(&mut *mime_types, empty_hash_set))
+    Ok(mem::take(&mut *mime_types).into_iter().collect())
 }
 
 /// Retrieves the clipboard contents.
@@ -243,6 +624,11 @@ pub(crate) fn get_mime_types_internal(clipboard: ClipboardType,
 /// This function returns a tuple of the reading end of a pipe containing the clipboard contents
 /// and the actual MIME type of the contents.
 ///
+/// The contents arrive through the pipe as the copying client writes them, so large contents
+/// can be streamed, e.g. into a file with `io::copy()`, without holding them in memory. Reading
+/// doesn't need the Wayland connection anymore. Dropping the pipe before reading everything
+/// closes it, which tells the copying client to stop writing.
+///
 /// If `seat` is `None`, uses an unspecified seat (it depends on the order returned by the
 /// compositor). This is perfectly fine when only a single seat is present, so for most
 /// configurations.
@@ -284,6 +670,45 @@ pub fn get_contents(clipboard: ClipboardType,
     get_contents_internal(clipboard, seat, mime_type, None)
 }
 
+/// Retrieves the clipboard contents using an existing connection.
+///
+/// This is the same as `get_contents()`, except that `connection` is used instead of connecting
+/// to the compositor anew.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate wl_clipboard_rs;
+/// # extern crate failure;
+/// # use failure::Error;
+/// # fn foo() -> Result<(), Error> {
+/// use std::io::Read;
+/// use wl_clipboard_rs::{connection::Connection,
+///                       paste::{get_contents_with_connection, ClipboardType, MimeType, Seat}};
+///
+/// let mut connection = Connection::new()?;
+/// for _ in 0..10 {
+///     let (mut pipe, _) = get_contents_with_connection(&mut connection,
+///                                                      ClipboardType::Regular,
+///                                                      Seat::Unspecified,
+///                                                      MimeType::Text)?;
+///     let mut contents = vec![];
+///     pipe.read_to_end(&mut contents)?;
+///     println!("Read {} bytes of data", contents.len());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn get_contents_with_connection(connection: &mut Connection,
+                                    clipboard: ClipboardType,
+                                    seat: Seat<'_>,
+                                    mime_type: MimeType<'_>)
+                                    -> Result<(PipeReader, String), Error> {
+    let primary = clipboard == ClipboardType::Primary;
+    check_version(&connection.common, primary)?;
+    get_contents_from(&mut connection.common, primary, seat, mime_type)
+}
+
 // The internal function accepts the socket name, used for tests.
 pub(crate) fn get_contents_internal(clipboard: ClipboardType,
                                     seat: Seat<'_>,
@@ -291,30 +716,164 @@ pub(crate) fn get_contents_internal(clipboard: ClipboardType,
                                     socket_name: Option<OsString>)
                                     -> Result<(PipeReader, String), Error> {
     let primary = clipboard == ClipboardType::Primary;
-    let (mut queue, offer) = get_offer(primary, seat, socket_name)?;
+    let mut common = initialize(primary, socket_name)?;
+    get_contents_from(&mut common, primary, seat, mime_type)
+}
 
-    let mut mime_types = offer.as_ref()
-                              .user_data::<RefCell<HashSet<String>>>()
-                              .unwrap()
-                              .borrow_mut();
+/// Reads the clipboard contents into the given buffer.
+///
+/// This is the same as `get_contents()`, except that the contents are read in full into `buf`,
+/// replacing its previous contents. Reusing the buffer avoids allocating a new one every time,
+/// which helps when reading the clipboard frequently, e.g. in a clipboard history daemon.
+///
+/// Returns the MIME type of the contents and the number of bytes read.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate wl_clipboard_rs;
+/// # use wl_clipboard_rs::paste::Error;
+/// # fn foo() -> Result<(), Error> {
+/// use wl_clipboard_rs::paste::{get_contents_into, ClipboardType, MimeType, Seat};
+///
+/// let mut buf = Vec::new();
+/// for _ in 0..10 {
+///     let (mime_type, len) =
+///         get_contents_into(ClipboardType::Regular, Seat::Unspecified, MimeType::Text, &mut buf)?;
+///     println!("Read {} bytes of {}", len, mime_type);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[inline]
+pub fn get_contents_into(clipboard: ClipboardType,
+                         seat: Seat<'_>,
+                         mime_type: MimeType<'_>,
+                         buf: &mut Vec<u8>)
+                         -> Result<(String, usize), Error> {
+    get_contents_into_internal(clipboard, seat, mime_type, buf, None)
+}
+
+/// Reads the clipboard contents into the given buffer using an existing connection.
+///
+/// This is the same as `get_contents_into()`, except that `connection` is used instead of
+/// connecting to the compositor anew.
+pub fn get_contents_into_with_connection(connection: &mut Connection,
+                                         clipboard: ClipboardType,
+                                         seat: Seat<'_>,
+                                         mime_type: MimeType<'_>,
+                                         buf: &mut Vec<u8>)
+                                         -> Result<(String, usize), Error> {
+    let (read, mime_type) = get_contents_with_connection(connection, clipboard, seat, mime_type)?;
+    read_into(read, mime_type, buf)
+}
+
+// The internal function accepts the socket name, used for tests.
+pub(crate) fn get_contents_into_internal(clipboard: ClipboardType,
+                                         seat: Seat<'_>,
+                                         mime_type: MimeType<'_>,
+                                         buf: &mut Vec<u8>,
+                                         socket_name: Option<OsString>)
+                                         -> Result<(String, usize), Error> {
+    let (read, mime_type) = get_contents_internal(clipboard, seat, mime_type, socket_name)?;
+    read_into(read, mime_type, buf)
+}
+
+/// Retrieves at most `max_bytes` bytes of the clipboard contents.
+///
+/// Returns the contents, the MIME type and whether the contents were truncated. Reading stops
+/// after `max_bytes` bytes and the pipe is closed, so the copying client doesn't have to send
+/// the rest. This is useful for previewing large clipboard contents.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate wl_clipboard_rs;
+/// # use wl_clipboard_rs::paste::Error;
+/// # fn foo() -> Result<(), Error> {
+/// use wl_clipboard_rs::paste::{get_contents_limited, ClipboardType, MimeType, Seat};
+///
+/// let (contents, _, truncated) =
+///     get_contents_limited(ClipboardType::Regular, Seat::Unspecified, MimeType::Text, 80)?;
+/// println!("{}{}",
+///          String::from_utf8_lossy(&contents),
+///          if truncated { "..." } else { "" });
+/// # Ok(())
+/// # }
+/// ```
+#[inline]
+pub fn get_contents_limited(clipboard: ClipboardType,
+                            seat: Seat<'_>,
+                            mime_type: MimeType<'_>,
+                            max_bytes: usize)
+                            -> Result<(Vec<u8>, String, bool), Error> {
+    get_contents_limited_internal(clipboard, seat, mime_type, max_bytes, None)
+}
+
+/// Retrieves at most `max_bytes` bytes of the clipboard contents using an existing connection.
+///
+/// This is the same as `get_contents_limited()`, except that `connection` is used instead of
+/// connecting to the compositor anew.
+pub fn get_contents_limited_with_connection(connection: &mut Connection,
+                                            clipboard: ClipboardType,
+                                            seat: Seat<'_>,
+                                            mime_type: MimeType<'_>,
+                                            max_bytes: usize)
+                                            -> Result<(Vec<u8>, String, bool), Error> {
+    let (read, mime_type) = get_contents_with_connection(connection, clipboard, seat, mime_type)?;
+    read_limited(read, mime_type, max_bytes)
+}
+
+// The internal function accepts the socket name, used for tests.
+pub(crate) fn get_contents_limited_internal(clipboard: ClipboardType,
+                                            seat: Seat<'_>,
+                                            mime_type: MimeType<'_>,
+                                            max_bytes: usize,
+                                            socket_name: Option<OsString>)
+                                            -> Result<(Vec<u8>, String, bool), Error> {
+    let (read, mime_type) = get_contents_internal(clipboard, seat, mime_type, socket_name)?;
+    read_limited(read, mime_type, max_bytes)
+}
+
+// Reads at most `max_bytes` bytes of `read`, telling whether there was more.
+fn read_limited(read: PipeReader,
+                mime_type: String,
+                max_bytes: usize)
+                -> Result<(Vec<u8>, String, bool), Error> {
+    // Read one byte more to tell whether there's anything past the limit.
+    let mut contents = Vec::new();
+    read.take(max_bytes as u64 + 1)
+        .read_to_end(&mut contents)
+        .map_err(Error::ContentsRead)?;
+
+    let truncated = contents.len() > max_bytes;
+    contents.truncate(max_bytes);
+    Ok((contents, mime_type, truncated))
+}
+
+// Reads all of `read` into `buf`, replacing its contents.
+fn read_into(mut read: PipeReader,
+             mime_type: String,
+             buf: &mut Vec<u8>)
+             -> Result<(String, usize), Error> {
+    buf.clear();
+    let len = read.read_to_end(buf).map_err(Error::ContentsRead)?;
+    Ok((mime_type, len))
+}
+
+fn get_contents_from(common: &mut CommonData,
+                     primary: bool,
+                     seat: Seat<'_>,
+                     mime_type: MimeType<'_>)
+                     -> Result<(PipeReader, String), Error> {
+    let offer = current_offer(common, primary, seat)?;
 
     // Find the desired MIME type.
-    let mime_type = match mime_type {
-        MimeType::Any => mime_types.take("text/plain;charset=utf-8")
-                                   .or_else(|| mime_types.take("UTF8_STRING"))
-                                   .or_else(|| mime_types.iter().find(|x| is_text(x)).cloned())
-                                   .or_else(|| mime_types.drain().next()),
-        MimeType::Text => mime_types.take("text/plain;charset=utf-8")
-                                    .or_else(|| mime_types.take("UTF8_STRING"))
-                                    .or_else(|| mime_types.drain().find(|x| is_text(x))),
-        MimeType::TextWithPriority(priority) => {
-            mime_types.take(priority)
-                      .or_else(|| mime_types.take("text/plain;charset=utf-8"))
-                      .or_else(|| mime_types.take("UTF8_STRING"))
-                      .or_else(|| mime_types.drain().find(|x| is_text(x)))
-        }
-        MimeType::Specific(mime_type) => mime_types.take(mime_type),
-    };
+    let mime_type = take_mime_type(&mut offer.as_ref()
+                                             .user_data::<RefCell<Vec<String>>>()
+                                             .unwrap()
+                                             .borrow_mut(),
+                                   mime_type);
 
     // Check if a suitable MIME type is copied.
     if mime_type.is_none() {
@@ -323,14 +882,99 @@ pub(crate) fn get_contents_internal(clipboard: ClipboardType,
 
     let mime_type = mime_type.unwrap();
 
-    // Create a pipe for content transfer.
-    let (read, write) = pipe().map_err(Error::PipeCreation)?;
+    let read = receive(&mut common.queue, &offer, mime_type.clone())?;
+    Ok((read, mime_type))
+}
 
-    // Start the transfer.
-    offer.receive(mime_type.clone(), write.as_raw_fd());
-    drop(write);
-    queue.sync_roundtrip()
-         .map_err(Error::WaylandCommunication)?;
+/// Watches the clipboard for changes.
+///
+/// Whenever the clipboard contents change, the new contents are requested in the `mime_type` MIME
+/// type and `callback` is invoked with the reading end of a pipe containing them and the actual
+/// MIME type of the contents, like the result of `get_contents()`. This also happens once for the
+/// contents present at the time of the call. Cleared clipboards and contents not available in a
+/// suitable MIME type are skipped.
+///
+/// If the clipboard changes several times in quick succession, only the latest contents are
+/// reported.
+///
+/// This function **blocks** until an error occurs, for example until the connection to the
+/// Wayland compositor is lost.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate wl_clipboard_rs;
+/// # use wl_clipboard_rs::paste::Error;
+/// # fn foo() -> Result<(), Error> {
+/// use std::io::Read;
+/// use wl_clipboard_rs::{paste::{watch, ClipboardType, MimeType, Seat}};
+///
+/// watch(ClipboardType::Regular, Seat::Unspecified, MimeType::Text, |mut pipe, _| {
+///     let mut contents = vec![];
+///     if pipe.read_to_end(&mut contents).is_ok() {
+///         println!("New clipboard contents: {}", String::from_utf8_lossy(&contents));
+///     }
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+#[inline]
+pub fn watch<F>(clipboard: ClipboardType,
+                seat: Seat<'_>,
+                mime_type: MimeType<'_>,
+                callback: F)
+                -> Result<(), Error>
+    where F: FnMut(PipeReader, String)
+{
+    watch_internal(clipboard, seat, mime_type, callback, None)
+}
 
-    Ok((read, mime_type))
+// The internal function accepts the socket name, used for tests.
+pub(crate) fn watch_internal<F>(clipboard: ClipboardType,
+                                seat: Seat<'_>,
+                                mime_type: MimeType<'_>,
+                                mut callback: F,
+                                socket_name: Option<OsString>)
+                                -> Result<(), Error>
+    where F: FnMut(PipeReader, String)
+{
+    let primary = clipboard == ClipboardType::Primary;
+    let mut common = initialize(primary, socket_name)?;
+    create_devices(&mut common, primary)?;
+    let CommonData { mut queue,
+                     seats,
+                     seat_globals,
+                     .. } = common;
+
+    let mut last_offer = None::<ZwlrDataControlOfferV1>;
+    loop {
+        // Several selection events may have been dispatched at once, only the last one matters.
+        // Seats may also have been removed in the meantime.
+        let offer = find_offer(&seats.borrow(), &seat_globals.borrow(), seat)?;
+
+        let changed = match (&offer, &last_offer) {
+            (Some(offer), Some(last_offer)) => !offer.as_ref().equals(last_offer.as_ref()),
+            (None, None) => false,
+            _ => true,
+        };
+
+        if changed {
+            if let Some(offer) = &offer {
+                let chosen = take_mime_type(&mut offer.as_ref()
+                                                      .user_data::<RefCell<Vec<String>>>()
+                                                      .unwrap()
+                                                      .borrow_mut(),
+                                            mime_type);
+
+                if let Some(chosen) = chosen {
+                    let read = receive(&mut queue, offer, chosen.clone())?;
+                    callback(read, chosen);
+                }
+            }
+
+            last_offer = offer;
+        }
+
+        queue.dispatch().map_err(Error::WaylandCommunication)?;
+    }
 }
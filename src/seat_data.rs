@@ -3,8 +3,10 @@ use wayland_protocols::wlr::unstable::data_control::v1::client::{
     zwlr_data_control_offer_v1::ZwlrDataControlOfferV1,
 };
 
-#[derive(Default)]
 pub struct SeatData {
+    /// The numeric name of the `wl_seat` global this seat was bound from.
+    pub global_id: u32,
+
     /// The name of this seat, if any.
     pub name: Option<String>,
 
@@ -18,6 +20,14 @@ pub struct SeatData {
 }
 
 impl SeatData {
+    /// Creates data for a seat bound from the given global.
+    pub fn new(global_id: u32) -> Self {
+        Self { global_id,
+               name: None,
+               device: None,
+               offer: None }
+    }
+
     /// Sets this seat's name.
     pub fn set_name(&mut self, name: String) {
         self.name = Some(name)
@@ -0,0 +1,133 @@
+use std::{ffi::OsString, mem, thread, time::Duration};
+
+use wayland_protocols::wlr::unstable::data_control::v1::server::zwlr_data_control_manager_v1::ZwlrDataControlManagerV1 as ServerManager;
+
+use crate::{common::*, tests::TestServer};
+
+fn bound_version(server_version: u32, primary: bool) -> Result<u32, Error> {
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerManager, _>(server_version, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        initialize(primary, Some(socket_name)).map(|common| {
+                                                  common.clipboard_manager.as_ref().version()
+                                              })
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    child.join().unwrap()
+}
+
+#[test]
+fn initialize_prefers_data_control_v2() {
+    assert_eq!(bound_version(2, false).unwrap(), 2);
+    assert_eq!(bound_version(2, true).unwrap(), 2);
+}
+
+#[test]
+fn initialize_falls_back_to_data_control_v1() {
+    assert_eq!(bound_version(1, false).unwrap(), 1);
+
+    let error = bound_version(1, true).unwrap_err();
+    if let Error::MissingProtocol { name, version } = error {
+        assert_eq!(name, "zwlr_data_control_manager_v1");
+        assert_eq!(version, 2);
+    } else {
+        panic!("Invalid error: {:?}", error);
+    }
+}
+
+#[test]
+fn check_version_test() {
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerManager, _>(1, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        connect(1, Some(socket_name)).map(|common| {
+                                         let regular = check_version(&common, false);
+                                         let primary = check_version(&common, true);
+                                         (regular, primary)
+                                     })
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let (regular, primary) = child.join().unwrap().unwrap();
+    regular.unwrap();
+    if let Err(Error::MissingProtocol { name, version }) = primary {
+        assert_eq!(name, "zwlr_data_control_manager_v1");
+        assert_eq!(version, 2);
+    } else {
+        panic!("Invalid result: {:?}", primary);
+    }
+}
+
+#[test]
+fn initialize_manager_removed_before_bind() {
+    let mut server = TestServer::new();
+    let global = server.display
+                       .create_global::<ServerManager, _>(2, |new_res, _| {
+                           new_res.implement_dummy();
+                       });
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || initialize(false, Some(socket_name)).map(|_| ()));
+
+    // Advertise the global, then remove it within the same roundtrip.
+    thread::sleep(Duration::from_millis(100));
+    server.event_loop
+          .dispatch(Some(Duration::from_millis(10)), &mut ())
+          .unwrap();
+    global.destroy();
+    server.display.flush_clients();
+
+    let error = child.join().unwrap().unwrap_err();
+    if let Error::GlobalBindFailed { name } = error {
+        assert_eq!(name, "zwlr_data_control_manager_v1");
+    } else {
+        panic!("Invalid error: {:?}", error);
+    }
+}
+
+#[test]
+fn initialize_picks_highest_manager_version() {
+    let mut server = TestServer::new();
+    for &version in &[1, 2, 1] {
+        server.display
+              .create_global::<ServerManager, _>(version, |new_res, _| {
+                  new_res.implement_dummy();
+              });
+    }
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        initialize(false, Some(socket_name)).map(|common| {
+                                                 common.clipboard_manager.as_ref().version()
+                                             })
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    assert_eq!(child.join().unwrap().unwrap(), 2);
+}
+
+#[test]
+fn pick_manager_test() {
+    assert_eq!(pick_manager(&[]), None);
+    assert_eq!(pick_manager(&[(5, 1)]), Some((5, 1)));
+    assert_eq!(pick_manager(&[(5, 1), (7, 2), (9, 1)]), Some((7, 2)));
+    assert_eq!(pick_manager(&[(5, 2), (7, 2)]), Some((5, 2)));
+    assert_eq!(pick_manager(&[(5, 3), (7, 2)]), Some((5, 3)));
+}
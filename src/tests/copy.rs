@@ -1,22 +1,33 @@
 use std::{
     cell::{Cell, RefCell},
+    collections::HashMap,
     ffi::OsString,
-    io::Read,
+    fs,
+    io::{self, Read, Write},
     mem,
-    os::unix::io::AsRawFd,
+    os::unix::{
+        ffi::OsStringExt,
+        io::AsRawFd,
+    },
     rc::Rc,
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use libc::STDIN_FILENO;
+use nix::{
+    fcntl::{fcntl, FcntlArg, OFlag},
+    poll::{poll, PollFd, PollFlags},
+    unistd::{close, dup, dup2, read},
+};
 use os_pipe::pipe;
 use wayland_protocols::wlr::unstable::data_control::v1::server::{
     zwlr_data_control_device_v1::{
         Request as ServerDeviceRequest, ZwlrDataControlDeviceV1 as ServerDevice,
     },
     zwlr_data_control_manager_v1::{
-        RequestHandler as ServerManagerRequestHandler, ZwlrDataControlManagerV1 as ServerManager,
+        Request as ServerManagerRequest, RequestHandler as ServerManagerRequestHandler,
+        ZwlrDataControlManagerV1 as ServerManager,
     },
     zwlr_data_control_source_v1::{
         Request as ServerSourceRequest, ZwlrDataControlSourceV1 as ServerSource,
@@ -24,7 +35,15 @@ use wayland_protocols::wlr::unstable::data_control::v1::server::{
 };
 use wayland_server::{protocol::wl_seat::WlSeat as ServerSeat, NewResource};
 
-use crate::{copy::*, tests::TestServer};
+use crate::{
+    connection::Connection,
+    copy::*,
+    paste::{
+        get_contents_internal, ClipboardType as PasteClipboardType, Error as PasteError,
+        MimeType as PasteMimeType, Seat as PasteSeat,
+    },
+    tests::TestServer,
+};
 
 #[test]
 fn clear_test() {
@@ -85,6 +104,68 @@ fn clear_test() {
     assert!(pass.get());
 }
 
+#[test]
+fn clear_primary_test() {
+    struct ServerManagerHandler {
+        pass: Rc<Cell<bool>>,
+    }
+
+    impl ServerManagerRequestHandler for ServerManagerHandler {
+        fn get_data_device(&mut self,
+                           _manager: ServerManager,
+                           id: NewResource<ServerDevice>,
+                           _seat: ServerSeat) {
+            let pass = self.pass.clone();
+            let device = id.implement_closure(move |request, _| {
+                                                  if let ServerDeviceRequest::SetPrimarySelection {
+                                                      source: None,
+                                                  } = request
+                                                  {
+                                                      pass.set(true);
+                                                  }
+                                              },
+                                              None::<fn(_)>,
+                                              ());
+            // Signal the primary selection support.
+            device.primary_selection(None);
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let pass = Rc::new(Cell::new(false));
+    {
+        let pass = pass.clone();
+        server.display
+              .create_global::<ServerManager, _>(2, move |new_res, _| {
+                  new_res.implement(ServerManagerHandler { pass: pass.clone() },
+                                    None::<fn(_)>,
+                                    ());
+              });
+    }
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child =
+        thread::spawn(move || clear_internal(ClipboardType::Primary, Seat::All, Some(socket_name)));
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    child.join().unwrap().unwrap();
+
+    assert!(pass.get());
+}
+
 #[test]
 fn copy_test() {
     struct ServerManagerHandler {
@@ -185,7 +266,7 @@ fn copy_test() {
 }
 
 #[test]
-fn copy_multi_test() {
+fn prepare_copy_dispatch_pending() {
     struct ServerManagerHandler {
         selection: Rc<RefCell<Option<ServerSource>>>,
     }
@@ -242,19 +323,17 @@ fn copy_multi_test() {
         let mut opts = Options::new();
         opts.foreground(true);
         let sources = vec![MimeSource { source: Source::Bytes([1, 3, 3, 7][..].into()),
-                                        mime_type: MimeType::Specific("test".to_string()) },
-                           MimeSource { source: Source::Bytes([2, 4, 4][..].into()),
-                                        mime_type: MimeType::Specific("test2".to_string()) },
-                           // Ignored because it's the second "test" MIME type.
-                           MimeSource { source: Source::Bytes([4, 3, 2, 1][..].into()),
-                                        mime_type: MimeType::Specific("test".to_string()) },
-                           // The first text source, additional text types should fall back here.
-                           MimeSource { source: Source::Bytes(b"hello fallback"[..].into()),
-                                        mime_type: MimeType::Text },
-                           // A specific override of an additional text type.
-                           MimeSource { source: Source::Bytes(b"hello TEXT"[..].into()),
-                                        mime_type: MimeType::Specific("TEXT".to_string()) },];
-        copy_internal(opts, sources, Some(socket_name))
+                                        mime_type: MimeType::Specific("test".to_string()) }];
+        let mut prepared_copy = prepare_copy_internal(opts, sources, Some(socket_name))?;
+
+        loop {
+            let mut fds = [PollFd::new(prepared_copy.connection_fd(), PollFlags::POLLIN)];
+            poll(&mut fds, -1).unwrap();
+
+            if prepared_copy.dispatch_pending()? {
+                break Ok::<_, Error>(());
+            }
+        }
     });
 
     thread::sleep(Duration::from_millis(100));
@@ -274,64 +353,28 @@ fn copy_multi_test() {
                                                      .clone()
                                                 });
 
-    let (mut read_test, write_test) = pipe().unwrap();
-    let (mut read_test2, write_test2) = pipe().unwrap();
-    let (mut read_fallback, write_fallback) = pipe().unwrap();
-    let (mut read_text, write_text) = pipe().unwrap();
+    let (mut read, write) = pipe().unwrap();
 
     if let Some(source) = selection.borrow().as_ref() {
-        source.send("test".to_string(), write_test.as_raw_fd());
-        drop(write_test);
-        source.send("test2".to_string(), write_test2.as_raw_fd());
-        drop(write_test2);
-        source.send("STRING".to_string(), write_fallback.as_raw_fd());
-        drop(write_fallback);
-        source.send("TEXT".to_string(), write_text.as_raw_fd());
-        drop(write_text);
+        source.send("test".to_string(), write.as_raw_fd());
+        drop(write);
         source.cancelled();
     }
 
     thread::sleep(Duration::from_millis(100));
     server.answer();
 
-    let mut contents_test = vec![];
-    read_test.read_to_end(&mut contents_test).unwrap();
-    let mut contents_test2 = vec![];
-    read_test2.read_to_end(&mut contents_test2).unwrap();
-    let mut contents_fallback = vec![];
-    read_fallback.read_to_end(&mut contents_fallback).unwrap();
-    let mut contents_text = vec![];
-    read_text.read_to_end(&mut contents_text).unwrap();
+    let mut contents = vec![];
+    read.read_to_end(&mut contents).unwrap();
 
     child.join().unwrap().unwrap();
 
-    assert!(mime_types.is_some());
-    let mut mimes = mime_types.unwrap();
-    mimes.sort();
-    assert_eq!(mimes,
-               ["STRING",
-                "TEXT",
-                "UTF8_STRING",
-                "test",
-                "test2",
-                "text/plain",
-                "text/plain;charset=utf-8"]);
-    assert_eq!(contents_test, [1, 3, 3, 7]);
-    assert_eq!(contents_test2, [2, 4, 4]);
-    assert_eq!(contents_fallback, b"hello fallback");
-    assert_eq!(contents_text, b"hello TEXT");
+    assert_eq!(mime_types, Some(vec!["test".to_string()]));
+    assert_eq!(contents, [1, 3, 3, 7]);
 }
 
-// The idea here is to exceed the pipe capacity. This fails unless O_NONBLOCK is cleared when
-// sending data over the pipe using cat.
 #[test]
-fn copy_large() {
-    // Assuming the default pipe capacity is 65536.
-    let mut bytes_to_copy = vec![];
-    for i in 0..70000 {
-        bytes_to_copy.push((i % 256) as u8);
-    }
-
+fn copy_empty() {
     struct ServerManagerHandler {
         selection: Rc<RefCell<Option<ServerSource>>>,
     }
@@ -383,17 +426,14 @@ fn copy_large() {
               });
     }
 
-    let child = {
-        let socket_name = mem::replace(&mut server.socket_name, OsString::new());
-        let bytes_to_copy = bytes_to_copy.clone();
-        thread::spawn(move || {
-            let mut opts = Options::new();
-            opts.foreground(true);
-            let sources = vec![MimeSource { source: Source::Bytes(bytes_to_copy.into()),
-                                            mime_type: MimeType::Specific("test".to_string()) }];
-            copy_internal(opts, sources, Some(socket_name))
-        })
-    };
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true);
+        let sources = vec![MimeSource { source: Source::Bytes(Vec::new().into()),
+                                        mime_type: MimeType::Specific("test".to_string()) }];
+        copy_internal(opts, sources, Some(socket_name))
+    });
 
     thread::sleep(Duration::from_millis(100));
     server.answer();
@@ -404,14 +444,18 @@ fn copy_large() {
     thread::sleep(Duration::from_millis(100));
     server.answer();
 
+    let mime_types = selection.borrow().as_ref().map(|x| {
+                                                    x.as_ref()
+                                                     .user_data::<RefCell<Vec<String>>>()
+                                                     .unwrap()
+                                                     .borrow()
+                                                     .clone()
+                                                });
+
     let (mut read, write) = pipe().unwrap();
 
     if let Some(source) = selection.borrow().as_ref() {
-        // Emulate what XWayland does and set O_NONBLOCK.
-        let fd = write.as_raw_fd();
-        fcntl(fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).unwrap();
-
-        source.send("test".to_string(), fd);
+        source.send("test".to_string(), write.as_raw_fd());
         drop(write);
         source.cancelled();
     }
@@ -424,6 +468,2219 @@ fn copy_large() {
 
     child.join().unwrap().unwrap();
 
-    assert_eq!(contents.len(), bytes_to_copy.len());
-    assert_eq!(contents, bytes_to_copy);
+    assert_eq!(mime_types, Some(vec!["test".to_string()]));
+    assert!(contents.is_empty());
+}
+
+#[test]
+fn copy_guarded_cancel() {
+    struct ServerManagerHandler {
+        selection: Rc<RefCell<Option<ServerSource>>>,
+    }
+
+    impl ServerManagerRequestHandler for ServerManagerHandler {
+        fn create_data_source(&mut self, _manager: ServerManager, id: NewResource<ServerSource>) {
+            id.implement_closure(|request, source| {
+                                     if let ServerSourceRequest::Offer { mime_type } = request {
+                                         source.as_ref()
+                                               .user_data::<RefCell<Vec<_>>>()
+                                               .unwrap()
+                                               .borrow_mut()
+                                               .push(mime_type);
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 RefCell::new(Vec::<String>::new()));
+        }
+
+        fn get_data_device(&mut self,
+                           _manager: ServerManager,
+                           id: NewResource<ServerDevice>,
+                           _seat: ServerSeat) {
+            let selection = self.selection.clone();
+            id.implement_closure(move |request, _| {
+                                     if let ServerDeviceRequest::SetSelection { source } = request {
+                                         *selection.borrow_mut() = source;
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 ());
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let selection = Rc::new(RefCell::new(None));
+    {
+        let selection = selection.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  new_res.implement(ServerManagerHandler { selection: selection.clone() },
+                                    None::<fn(_)>,
+                                    ());
+              });
+    }
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let sources = vec![MimeSource { source: Source::Bytes([1, 3, 3, 7][..].into()),
+                                        mime_type: MimeType::Specific("test".to_string()) }];
+        let guard = copy_guarded_internal(Options::new(), sources, Some(socket_name))?;
+        guard.cancel()
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    child.join().unwrap().unwrap();
+    assert!(selection.borrow().as_ref().unwrap().as_ref().is_alive());
+
+    // Let the server notice that the selection was given up.
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    assert!(!selection.borrow().as_ref().unwrap().as_ref().is_alive());
+}
+
+#[test]
+fn copy_to_seats_test() {
+    let mut server = TestServer::new();
+    for &name in &["first", "second", "third"] {
+        server.display
+              .create_global::<ServerSeat, _>(6, move |new_res, _| {
+                  let seat = new_res.implement_closure(|_, _| {}, None::<fn(_)>, name.to_string());
+                  seat.name(name.to_string());
+              });
+    }
+
+    let selections = Rc::new(RefCell::new(Vec::<(String, ServerSource)>::new()));
+    {
+        let selections = selections.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  let selections = selections.clone();
+                  new_res.implement_closure(move |request, _| match request {
+                                                ServerManagerRequest::CreateDataSource { id } => {
+                                                    id.implement_dummy();
+                                                }
+                                                ServerManagerRequest::GetDataDevice { id,
+                                                                                      seat, } => {
+                                                    let name = seat.as_ref()
+                                                                   .user_data::<String>()
+                                                                   .unwrap()
+                                                                   .clone();
+                                                    let selections = selections.clone();
+                                                    id.implement_closure(move |request, _| {
+                                               if let ServerDeviceRequest::SetSelection { source: Some(source) } = request {
+                                                   selections.borrow_mut().push((name.clone(), source));
+                                               }
+                                           },
+                                                                         None::<fn(_)>,
+                                                                         ());
+                                                }
+                                                _ => unreachable!(),
+                                            },
+                                            None::<fn(_)>,
+                                            ());
+              });
+    }
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true);
+        let mut sources = HashMap::new();
+        sources.insert(Seat::Specific("first".to_string()),
+                       (MimeType::Specific("test".to_string()), Source::Bytes(b"1"[..].into())));
+        sources.insert(Seat::All,
+                       (MimeType::Specific("test".to_string()), Source::Bytes(b"rest"[..].into())));
+        copy_to_seats_internal(opts, sources, Some(socket_name))
+    });
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    let mut contents = HashMap::new();
+    for (name, source) in selections.borrow().iter() {
+        let (mut read, write) = pipe().unwrap();
+        source.send("test".to_string(), write.as_raw_fd());
+        drop(write);
+        source.cancelled();
+
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+
+        let mut data = vec![];
+        read.read_to_end(&mut data).unwrap();
+        contents.insert(name.clone(), data);
+    }
+
+    child.join().unwrap().unwrap();
+
+    assert_eq!(contents.len(), 3);
+    assert_eq!(contents["first"], b"1");
+    assert_eq!(contents["second"], b"rest");
+    assert_eq!(contents["third"], b"rest");
+}
+
+#[test]
+fn copy_to_seats_duplicate() {
+    let mut server = TestServer::new();
+    for &name in &["first", "second"] {
+        server.display
+              .create_global::<ServerSeat, _>(6, move |new_res, _| {
+                  let seat = new_res.implement_dummy();
+                  seat.name(name.to_string());
+              });
+    }
+    server.create_clipboard_manager(1);
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true);
+        // Both the name and the index of the first seat.
+        let mut sources = HashMap::new();
+        sources.insert(Seat::Specific("first".to_string()),
+                       (MimeType::Specific("test".to_string()), Source::Bytes(b"1"[..].into())));
+        sources.insert(Seat::Specific("0".to_string()),
+                       (MimeType::Specific("test".to_string()), Source::Bytes(b"2"[..].into())));
+        copy_to_seats_internal(opts, sources, Some(socket_name))
+    });
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    let error = child.join().unwrap().unwrap_err();
+    if let Error::DuplicateSeat = error {
+        // Pass
+    } else {
+        panic!("Invalid error: {:?}", error);
+    }
+}
+
+#[test]
+fn copy_omit_additional_text_mime_types() {
+    struct ServerManagerHandler {
+        selection: Rc<RefCell<Option<ServerSource>>>,
+    }
+
+    impl ServerManagerRequestHandler for ServerManagerHandler {
+        fn create_data_source(&mut self, _manager: ServerManager, id: NewResource<ServerSource>) {
+            id.implement_closure(|request, source| {
+                                     if let ServerSourceRequest::Offer { mime_type } = request {
+                                         source.as_ref()
+                                               .user_data::<RefCell<Vec<_>>>()
+                                               .unwrap()
+                                               .borrow_mut()
+                                               .push(mime_type);
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 RefCell::new(Vec::<String>::new()));
+        }
+
+        fn get_data_device(&mut self,
+                           _manager: ServerManager,
+                           id: NewResource<ServerDevice>,
+                           _seat: ServerSeat) {
+            let selection = self.selection.clone();
+            id.implement_closure(move |request, _| {
+                                     if let ServerDeviceRequest::SetSelection { source } = request {
+                                         *selection.borrow_mut() = source;
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 ());
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let selection = Rc::new(RefCell::new(None));
+    {
+        let selection = selection.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  new_res.implement(ServerManagerHandler { selection: selection.clone() },
+                                    None::<fn(_)>,
+                                    ());
+              });
+    }
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true)
+            .omit_additional_text_mime_types(true);
+        let sources = vec![MimeSource { source: Source::Bytes(b"hello"[..].into()),
+                                        mime_type: MimeType::Text }];
+        copy_internal(opts, sources, Some(socket_name))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let mime_types = selection.borrow().as_ref().map(|x| {
+                                                    x.as_ref()
+                                                     .user_data::<RefCell<Vec<String>>>()
+                                                     .unwrap()
+                                                     .borrow()
+                                                     .clone()
+                                                });
+
+    let (mut read, write) = pipe().unwrap();
+
+    if let Some(source) = selection.borrow().as_ref() {
+        source.send("text/plain".to_string(), write.as_raw_fd());
+        drop(write);
+        source.cancelled();
+    }
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let mut contents = vec![];
+    read.read_to_end(&mut contents).unwrap();
+
+    child.join().unwrap().unwrap();
+
+    assert_eq!(mime_types, Some(vec!["text/plain".to_string()]));
+    assert_eq!(contents, b"hello");
+}
+
+#[test]
+fn copy_offers_normalized_mime_types_in_order() {
+    struct ServerManagerHandler {
+        selection: Rc<RefCell<Option<ServerSource>>>,
+    }
+
+    impl ServerManagerRequestHandler for ServerManagerHandler {
+        fn create_data_source(&mut self, _manager: ServerManager, id: NewResource<ServerSource>) {
+            id.implement_closure(|request, source| {
+                                     if let ServerSourceRequest::Offer { mime_type } = request {
+                                         source.as_ref()
+                                               .user_data::<RefCell<Vec<_>>>()
+                                               .unwrap()
+                                               .borrow_mut()
+                                               .push(mime_type);
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 RefCell::new(Vec::<String>::new()));
+        }
+
+        fn get_data_device(&mut self,
+                           _manager: ServerManager,
+                           id: NewResource<ServerDevice>,
+                           _seat: ServerSeat) {
+            let selection = self.selection.clone();
+            id.implement_closure(move |request, _| {
+                                     if let ServerDeviceRequest::SetSelection { source } = request {
+                                         *selection.borrow_mut() = source;
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 ());
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let selection = Rc::new(RefCell::new(None));
+    {
+        let selection = selection.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  new_res.implement(ServerManagerHandler { selection: selection.clone() },
+                                    None::<fn(_)>,
+                                    ());
+              });
+    }
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true);
+        let mime_type = MimeType::Specific("Text/Plain; Charset=UTF-8".to_string());
+        let sources = vec![MimeSource { source: Source::Bytes(b"hello"[..].into()),
+                                        mime_type },
+                           MimeSource { source: Source::Bytes(b"world"[..].into()),
+                                        mime_type: MimeType::Text }];
+        copy_internal(opts, sources, Some(socket_name))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let mime_types = selection.borrow().as_ref().map(|x| {
+                                                    x.as_ref()
+                                                     .user_data::<RefCell<Vec<String>>>()
+                                                     .unwrap()
+                                                     .borrow()
+                                                     .clone()
+                                                });
+
+    let (mut read, write) = pipe().unwrap();
+
+    if let Some(source) = selection.borrow().as_ref() {
+        source.send("text/plain".to_string(), write.as_raw_fd());
+        drop(write);
+        source.cancelled();
+    }
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let mut contents = vec![];
+    read.read_to_end(&mut contents).unwrap();
+
+    child.join().unwrap().unwrap();
+
+    assert_eq!(mime_types.unwrap(),
+               ["text/plain;charset=utf-8",
+                "text/plain",
+                "STRING",
+                "UTF8_STRING",
+                "TEXT"]);
+    assert_eq!(contents, b"world");
+}
+
+#[test]
+fn copy_additional_mime_types() {
+    struct ServerManagerHandler {
+        selection: Rc<RefCell<Option<ServerSource>>>,
+    }
+
+    impl ServerManagerRequestHandler for ServerManagerHandler {
+        fn create_data_source(&mut self, _manager: ServerManager, id: NewResource<ServerSource>) {
+            id.implement_closure(|request, source| {
+                                     if let ServerSourceRequest::Offer { mime_type } = request {
+                                         source.as_ref()
+                                               .user_data::<RefCell<Vec<_>>>()
+                                               .unwrap()
+                                               .borrow_mut()
+                                               .push(mime_type);
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 RefCell::new(Vec::<String>::new()));
+        }
+
+        fn get_data_device(&mut self,
+                           _manager: ServerManager,
+                           id: NewResource<ServerDevice>,
+                           _seat: ServerSeat) {
+            let selection = self.selection.clone();
+            id.implement_closure(move |request, _| {
+                                     if let ServerDeviceRequest::SetSelection { source } = request {
+                                         *selection.borrow_mut() = source;
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 ());
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let selection = Rc::new(RefCell::new(None));
+    {
+        let selection = selection.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  new_res.implement(ServerManagerHandler { selection: selection.clone() },
+                                    None::<fn(_)>,
+                                    ());
+              });
+    }
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true)
+            .additional_mime_types(vec!["application/json".to_string(),
+                                        "application/x-test".to_string(),
+                                        "test".to_string()]);
+        let sources = vec![MimeSource { source: Source::Bytes(b"hello"[..].into()),
+                                        mime_type: MimeType::Specific("test".to_string()) },
+                           MimeSource { source: Source::Bytes(b"world"[..].into()),
+                                        mime_type: MimeType::Specific("other".to_string()) }];
+        copy_internal(opts, sources, Some(socket_name))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let mime_types = selection.borrow().as_ref().map(|x| {
+                                                    x.as_ref()
+                                                     .user_data::<RefCell<Vec<String>>>()
+                                                     .unwrap()
+                                                     .borrow()
+                                                     .clone()
+                                                });
+
+    let (mut read, write) = pipe().unwrap();
+
+    if let Some(source) = selection.borrow().as_ref() {
+        source.send("application/json".to_string(), write.as_raw_fd());
+        drop(write);
+        source.cancelled();
+    }
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let mut contents = vec![];
+    read.read_to_end(&mut contents).unwrap();
+
+    child.join().unwrap().unwrap();
+
+    assert_eq!(mime_types.unwrap(),
+               ["test", "other", "application/json", "application/x-test"]);
+    assert_eq!(contents, b"hello");
+}
+
+#[test]
+fn copy_text_mime_types() {
+    struct ServerManagerHandler {
+        selection: Rc<RefCell<Option<ServerSource>>>,
+    }
+
+    impl ServerManagerRequestHandler for ServerManagerHandler {
+        fn create_data_source(&mut self, _manager: ServerManager, id: NewResource<ServerSource>) {
+            id.implement_closure(|request, source| {
+                                     if let ServerSourceRequest::Offer { mime_type } = request {
+                                         source.as_ref()
+                                               .user_data::<RefCell<Vec<_>>>()
+                                               .unwrap()
+                                               .borrow_mut()
+                                               .push(mime_type);
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 RefCell::new(Vec::<String>::new()));
+        }
+
+        fn get_data_device(&mut self,
+                           _manager: ServerManager,
+                           id: NewResource<ServerDevice>,
+                           _seat: ServerSeat) {
+            let selection = self.selection.clone();
+            id.implement_closure(move |request, _| {
+                                     if let ServerDeviceRequest::SetSelection { source } = request {
+                                         *selection.borrow_mut() = source;
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 ());
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let selection = Rc::new(RefCell::new(None));
+    {
+        let selection = selection.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  new_res.implement(ServerManagerHandler { selection: selection.clone() },
+                                    None::<fn(_)>,
+                                    ());
+              });
+    }
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true)
+            .trim_newline(true)
+            .text_mime_types(vec!["Application/X-Test".to_string()]);
+        let mime_type = MimeType::Specific("application/x-test; charset=utf-8".to_string());
+        let sources = vec![MimeSource { source: Source::Bytes(b"hello\n"[..].into()),
+                                        mime_type }];
+        copy_internal(opts, sources, Some(socket_name))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let mime_types = selection.borrow().as_ref().map(|x| {
+                                                    x.as_ref()
+                                                     .user_data::<RefCell<Vec<String>>>()
+                                                     .unwrap()
+                                                     .borrow()
+                                                     .clone()
+                                                });
+
+    let (mut read, write) = pipe().unwrap();
+
+    if let Some(source) = selection.borrow().as_ref() {
+        source.send("UTF8_STRING".to_string(), write.as_raw_fd());
+        drop(write);
+        source.cancelled();
+    }
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let mut contents = vec![];
+    read.read_to_end(&mut contents).unwrap();
+
+    child.join().unwrap().unwrap();
+
+    let mime_types = mime_types.unwrap();
+    assert_eq!(mime_types[0], "application/x-test;charset=utf-8");
+    assert!(mime_types.contains(&"UTF8_STRING".to_string()));
+    assert_eq!(contents, b"hello");
+}
+
+#[test]
+fn copy_empty_additional_mime_type() {
+    let mut opts = Options::new();
+    opts.foreground(true)
+        .additional_mime_types(vec!["application/json".to_string(), String::new()]);
+    let sources = vec![MimeSource { source: Source::Bytes(b"hello"[..].into()),
+                                    mime_type: MimeType::Specific("test".to_string()) }];
+
+    let error = copy_internal(opts, sources, None).unwrap_err();
+    if let Error::EmptyAdditionalMimeType = error {
+        // Pass
+    } else {
+        panic!("Invalid error: {:?}", error);
+    }
+}
+
+#[test]
+fn copy_with_connection_requires_foreground() {
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.display
+          .create_global::<ServerManager, _>(1, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let mut connection = Connection::new_internal(Some(socket_name)).unwrap();
+        copy_with_connection(&mut connection,
+                             Options::new(),
+                             Source::Bytes(b"hello"[..].into()),
+                             MimeType::Autodetect)
+    });
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    let error = child.join().unwrap().unwrap_err();
+    if let Error::ForegroundRequired = error {
+        // Pass
+    } else {
+        panic!("Invalid error: {:?}", error);
+    }
+}
+
+#[test]
+fn copy_html_plain_text() {
+    struct ServerManagerHandler {
+        selection: Rc<RefCell<Option<ServerSource>>>,
+    }
+
+    impl ServerManagerRequestHandler for ServerManagerHandler {
+        fn create_data_source(&mut self, _manager: ServerManager, id: NewResource<ServerSource>) {
+            id.implement_closure(|request, source| {
+                                     if let ServerSourceRequest::Offer { mime_type } = request {
+                                         source.as_ref()
+                                               .user_data::<RefCell<Vec<_>>>()
+                                               .unwrap()
+                                               .borrow_mut()
+                                               .push(mime_type);
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 RefCell::new(Vec::<String>::new()));
+        }
+
+        fn get_data_device(&mut self,
+                           _manager: ServerManager,
+                           id: NewResource<ServerDevice>,
+                           _seat: ServerSeat) {
+            let selection = self.selection.clone();
+            id.implement_closure(move |request, _| {
+                                     if let ServerDeviceRequest::SetSelection { source } = request {
+                                         *selection.borrow_mut() = source;
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 ());
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let selection = Rc::new(RefCell::new(None));
+    {
+        let selection = selection.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  new_res.implement(ServerManagerHandler { selection: selection.clone() },
+                                    None::<fn(_)>,
+                                    ());
+              });
+    }
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true);
+        let html = b"<b>fish &amp; chips</b>";
+        let sources = vec![MimeSource { source: Source::Bytes(html[..].into()),
+                                        mime_type: MimeType::Specific("text/html".to_string()) }];
+        copy_internal(opts, sources, Some(socket_name))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let mime_types = selection.borrow().as_ref().map(|x| {
+                                                    x.as_ref()
+                                                     .user_data::<RefCell<Vec<String>>>()
+                                                     .unwrap()
+                                                     .borrow()
+                                                     .clone()
+                                                });
+
+    let (mut read, write) = pipe().unwrap();
+
+    if let Some(source) = selection.borrow().as_ref() {
+        source.send("text/plain".to_string(), write.as_raw_fd());
+        drop(write);
+        source.cancelled();
+    }
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let mut contents = vec![];
+    read.read_to_end(&mut contents).unwrap();
+
+    child.join().unwrap().unwrap();
+
+    assert!(mime_types.unwrap().contains(&"text/html".to_string()));
+    assert_eq!(contents, b"fish & chips");
+}
+
+#[test]
+fn copy_multi_test() {
+    struct ServerManagerHandler {
+        selection: Rc<RefCell<Option<ServerSource>>>,
+    }
+
+    impl ServerManagerRequestHandler for ServerManagerHandler {
+        fn create_data_source(&mut self, _manager: ServerManager, id: NewResource<ServerSource>) {
+            id.implement_closure(|request, source| {
+                                     if let ServerSourceRequest::Offer { mime_type } = request {
+                                         source.as_ref()
+                                               .user_data::<RefCell<Vec<_>>>()
+                                               .unwrap()
+                                               .borrow_mut()
+                                               .push(mime_type);
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 RefCell::new(Vec::<String>::new()));
+        }
+
+        fn get_data_device(&mut self,
+                           _manager: ServerManager,
+                           id: NewResource<ServerDevice>,
+                           _seat: ServerSeat) {
+            let selection = self.selection.clone();
+            id.implement_closure(move |request, _| {
+                                     if let ServerDeviceRequest::SetSelection { source } = request {
+                                         *selection.borrow_mut() = source;
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 ());
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let selection = Rc::new(RefCell::new(None));
+    {
+        let selection = selection.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  new_res.implement(ServerManagerHandler { selection: selection.clone() },
+                                    None::<fn(_)>,
+                                    ());
+              });
+    }
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true);
+        let sources = vec![MimeSource { source: Source::Bytes([1, 3, 3, 7][..].into()),
+                                        mime_type: MimeType::Specific("test".to_string()) },
+                           MimeSource { source: Source::Bytes([2, 4, 4][..].into()),
+                                        mime_type: MimeType::Specific("test2".to_string()) },
+                           // Ignored because it's the second "test" MIME type.
+                           MimeSource { source: Source::Bytes([4, 3, 2, 1][..].into()),
+                                        mime_type: MimeType::Specific("test".to_string()) },
+                           // The first text source, additional text types should fall back here.
+                           MimeSource { source: Source::Bytes(b"hello fallback"[..].into()),
+                                        mime_type: MimeType::Text },
+                           // A specific override of an additional text type.
+                           MimeSource { source: Source::Bytes(b"hello TEXT"[..].into()),
+                                        mime_type: MimeType::Specific("TEXT".to_string()) },];
+        copy_internal(opts, sources, Some(socket_name))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let mime_types = selection.borrow().as_ref().map(|x| {
+                                                    x.as_ref()
+                                                     .user_data::<RefCell<Vec<String>>>()
+                                                     .unwrap()
+                                                     .borrow()
+                                                     .clone()
+                                                });
+
+    let (mut read_test, write_test) = pipe().unwrap();
+    let (mut read_test2, write_test2) = pipe().unwrap();
+    let (mut read_fallback, write_fallback) = pipe().unwrap();
+    let (mut read_text, write_text) = pipe().unwrap();
+
+    if let Some(source) = selection.borrow().as_ref() {
+        source.send("test".to_string(), write_test.as_raw_fd());
+        drop(write_test);
+        source.send("test2".to_string(), write_test2.as_raw_fd());
+        drop(write_test2);
+        source.send("STRING".to_string(), write_fallback.as_raw_fd());
+        drop(write_fallback);
+        source.send("TEXT".to_string(), write_text.as_raw_fd());
+        drop(write_text);
+        source.cancelled();
+    }
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let mut contents_test = vec![];
+    read_test.read_to_end(&mut contents_test).unwrap();
+    let mut contents_test2 = vec![];
+    read_test2.read_to_end(&mut contents_test2).unwrap();
+    let mut contents_fallback = vec![];
+    read_fallback.read_to_end(&mut contents_fallback).unwrap();
+    let mut contents_text = vec![];
+    read_text.read_to_end(&mut contents_text).unwrap();
+
+    child.join().unwrap().unwrap();
+
+    assert!(mime_types.is_some());
+    let mut mimes = mime_types.unwrap();
+    mimes.sort();
+    assert_eq!(mimes,
+               ["STRING",
+                "TEXT",
+                "UTF8_STRING",
+                "test",
+                "test2",
+                "text/plain",
+                "text/plain;charset=utf-8"]);
+    assert_eq!(contents_test, [1, 3, 3, 7]);
+    assert_eq!(contents_test2, [2, 4, 4]);
+    assert_eq!(contents_fallback, b"hello fallback");
+    assert_eq!(contents_text, b"hello TEXT");
+}
+
+#[test]
+fn copy_cancelled_test() {
+    struct ServerManagerHandler {
+        selection: Rc<RefCell<Option<ServerSource>>>,
+    }
+
+    impl ServerManagerRequestHandler for ServerManagerHandler {
+        fn create_data_source(&mut self, _manager: ServerManager, id: NewResource<ServerSource>) {
+            id.implement_dummy();
+        }
+
+        fn get_data_device(&mut self,
+                           _manager: ServerManager,
+                           id: NewResource<ServerDevice>,
+                           _seat: ServerSeat) {
+            let selection = self.selection.clone();
+            id.implement_closure(move |request, _| {
+                                     if let ServerDeviceRequest::SetSelection { source } = request {
+                                         *selection.borrow_mut() = source;
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 ());
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let selection = Rc::new(RefCell::new(None));
+    {
+        let selection = selection.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  new_res.implement(ServerManagerHandler { selection: selection.clone() },
+                                    None::<fn(_)>,
+                                    ());
+              });
+    }
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true);
+        let sources = vec![MimeSource { source: Source::Bytes([1, 3, 3, 7][..].into()),
+                                        mime_type: MimeType::Specific("test".to_string()) }];
+        copy_internal(opts, sources, Some(socket_name))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    // Someone else took over the clipboard without pasting our data.
+    selection.borrow().as_ref().unwrap().cancelled();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    // Serving should stop once the only source is cancelled.
+    child.join().unwrap().unwrap();
+}
+
+// The idea here is to exceed the pipe capacity. This fails unless O_NONBLOCK is cleared when
+// sending data over the pipe using cat.
+#[test]
+fn copy_large() {
+    // Assuming the default pipe capacity is 65536.
+    let mut bytes_to_copy = vec![];
+    for i in 0..70000 {
+        bytes_to_copy.push((i % 256) as u8);
+    }
+
+    struct ServerManagerHandler {
+        selection: Rc<RefCell<Option<ServerSource>>>,
+    }
+
+    impl ServerManagerRequestHandler for ServerManagerHandler {
+        fn create_data_source(&mut self, _manager: ServerManager, id: NewResource<ServerSource>) {
+            id.implement_closure(|request, source| {
+                                     if let ServerSourceRequest::Offer { mime_type } = request {
+                                         source.as_ref()
+                                               .user_data::<RefCell<Vec<_>>>()
+                                               .unwrap()
+                                               .borrow_mut()
+                                               .push(mime_type);
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 RefCell::new(Vec::<String>::new()));
+        }
+
+        fn get_data_device(&mut self,
+                           _manager: ServerManager,
+                           id: NewResource<ServerDevice>,
+                           _seat: ServerSeat) {
+            let selection = self.selection.clone();
+            id.implement_closure(move |request, _| {
+                                     if let ServerDeviceRequest::SetSelection { source } = request {
+                                         *selection.borrow_mut() = source;
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 ());
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let selection = Rc::new(RefCell::new(None));
+    {
+        let selection = selection.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  new_res.implement(ServerManagerHandler { selection: selection.clone() },
+                                    None::<fn(_)>,
+                                    ());
+              });
+    }
+
+    let child = {
+        let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+        let bytes_to_copy = bytes_to_copy.clone();
+        thread::spawn(move || {
+            let mut opts = Options::new();
+            opts.foreground(true);
+            let sources = vec![MimeSource { source: Source::Bytes(bytes_to_copy.into()),
+                                            mime_type: MimeType::Specific("test".to_string()) }];
+            copy_internal(opts, sources, Some(socket_name))
+        })
+    };
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let (mut read, write) = pipe().unwrap();
+
+    if let Some(source) = selection.borrow().as_ref() {
+        // Emulate what XWayland does and set O_NONBLOCK.
+        let fd = write.as_raw_fd();
+        fcntl(fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).unwrap();
+
+        source.send("test".to_string(), fd);
+        drop(write);
+        source.cancelled();
+    }
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let mut contents = vec![];
+    read.read_to_end(&mut contents).unwrap();
+
+    child.join().unwrap().unwrap();
+
+    assert_eq!(contents.len(), bytes_to_copy.len());
+    assert_eq!(contents, bytes_to_copy);
+}
+
+#[test]
+fn copy_slow_and_closed_readers() {
+    let mut bytes_to_copy = vec![];
+    for i in 0..60000 {
+        bytes_to_copy.push((i % 256) as u8);
+    }
+
+    struct ServerManagerHandler {
+        selection: Rc<RefCell<Option<ServerSource>>>,
+    }
+
+    impl ServerManagerRequestHandler for ServerManagerHandler {
+        fn create_data_source(&mut self, _manager: ServerManager, id: NewResource<ServerSource>) {
+            id.implement_dummy();
+        }
+
+        fn get_data_device(&mut self,
+                           _manager: ServerManager,
+                           id: NewResource<ServerDevice>,
+                           _seat: ServerSeat) {
+            let selection = self.selection.clone();
+            id.implement_closure(move |request, _| {
+                                     if let ServerDeviceRequest::SetSelection { source } = request {
+                                         *selection.borrow_mut() = source;
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 ());
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let selection = Rc::new(RefCell::new(None));
+    {
+        let selection = selection.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  new_res.implement(ServerManagerHandler { selection: selection.clone() },
+                                    None::<fn(_)>,
+                                    ());
+              });
+    }
+
+    let child = {
+        let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+        let bytes_to_copy = bytes_to_copy.clone();
+        thread::spawn(move || {
+            let mut opts = Options::new();
+            opts.foreground(true)
+                .serve_requests(ServeRequests::Only(2));
+            let sources = vec![MimeSource { source: Source::Bytes(bytes_to_copy.into()),
+                                            mime_type: MimeType::Specific("test".to_string()) }];
+            copy_internal(opts, sources, Some(socket_name))
+        })
+    };
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    // A small pipe, so that the data can only be written in parts.
+    let send = |server: &mut TestServer| {
+        let (read, write) = pipe().unwrap();
+        let fd = write.as_raw_fd();
+        fcntl(fd, FcntlArg::F_SETPIPE_SZ(4096)).unwrap();
+        fcntl(fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).unwrap();
+
+        selection.borrow().as_ref().unwrap().send("test".to_string(), fd);
+        drop(write);
+        server.answer();
+        read
+    };
+
+    // The first reader reads slowly in small chunks.
+    let mut read = send(&mut server);
+    let mut contents = vec![];
+    let mut buf = [0; 1000];
+    loop {
+        let count = read.read(&mut buf).unwrap();
+        if count == 0 {
+            break;
+        }
+        contents.extend_from_slice(&buf[..count]);
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(contents, bytes_to_copy);
+
+    // The second reader gives up early, which isn't an error.
+    let mut read = send(&mut server);
+    read.read_exact(&mut buf).unwrap();
+    drop(read);
+
+    child.join().unwrap().unwrap();
+}
+
+#[test]
+fn copy_stuck_reader() {
+    // Small enough to be kept in memory.
+    let mut bytes_to_copy = vec![];
+    for i in 0..60000 {
+        bytes_to_copy.push((i % 256) as u8);
+    }
+
+    struct ServerManagerHandler {
+        selection: Rc<RefCell<Option<ServerSource>>>,
+    }
+
+    impl ServerManagerRequestHandler for ServerManagerHandler {
+        fn create_data_source(&mut self, _manager: ServerManager, id: NewResource<ServerSource>) {
+            id.implement_dummy();
+        }
+
+        fn get_data_device(&mut self,
+                           _manager: ServerManager,
+                           id: NewResource<ServerDevice>,
+                           _seat: ServerSeat) {
+            let selection = self.selection.clone();
+            id.implement_closure(move |request, _| {
+                                     if let ServerDeviceRequest::SetSelection { source } = request {
+                                         *selection.borrow_mut() = source;
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 ());
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let selection = Rc::new(RefCell::new(None));
+    {
+        let selection = selection.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  new_res.implement(ServerManagerHandler { selection: selection.clone() },
+                                    None::<fn(_)>,
+                                    ());
+              });
+    }
+
+    let child = {
+        let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+        let bytes_to_copy = bytes_to_copy.clone();
+        thread::spawn(move || {
+            let mut opts = Options::new();
+            opts.foreground(true)
+                .serve_requests(ServeRequests::Only(2));
+            let sources = vec![MimeSource { source: Source::Bytes(bytes_to_copy.into()),
+                                            mime_type: MimeType::Specific("test".to_string()) }];
+            copy_internal(opts, sources, Some(socket_name))
+        })
+    };
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    // The first reader never reads, and its pipe fills up.
+    let (stuck_read, write) = pipe().unwrap();
+    fcntl(write.as_raw_fd(), FcntlArg::F_SETPIPE_SZ(4096)).unwrap();
+    selection.borrow().as_ref().unwrap().send("test".to_string(), write.as_raw_fd());
+    drop(write);
+    server.answer();
+
+    // The second request is still served.
+    let (mut read, write) = pipe().unwrap();
+    selection.borrow().as_ref().unwrap().send("test".to_string(), write.as_raw_fd());
+    drop(write);
+    server.answer();
+
+    let mut contents = vec![];
+    read.read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, bytes_to_copy);
+
+    child.join().unwrap().unwrap();
+    drop(stuck_read);
+}
+
+#[test]
+fn copy_continues_after_reader_closes() {
+    struct ServerManagerHandler {
+        selection: Rc<RefCell<Option<ServerSource>>>,
+    }
+
+    impl ServerManagerRequestHandler for ServerManagerHandler {
+        fn create_data_source(&mut self, _manager: ServerManager, id: NewResource<ServerSource>) {
+            id.implement_dummy();
+        }
+
+        fn get_data_device(&mut self,
+                           _manager: ServerManager,
+                           id: NewResource<ServerDevice>,
+                           _seat: ServerSeat) {
+            let selection = self.selection.clone();
+            id.implement_closure(move |request, _| {
+                                     if let ServerDeviceRequest::SetSelection { source } = request {
+                                         *selection.borrow_mut() = source;
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 ());
+        }
+    }
+
+    // Both the in-memory and the temporary file sources.
+    for &size in &[1000, 70000] {
+        let mut bytes_to_copy = vec![];
+        for i in 0..size {
+            bytes_to_copy.push((i % 256) as u8);
+        }
+
+        let mut server = TestServer::new();
+        server.display
+              .create_global::<ServerSeat, _>(6, |new_res, _| {
+                  new_res.implement_dummy();
+              });
+
+        let selection = Rc::new(RefCell::new(None));
+        {
+            let selection = selection.clone();
+            server.display
+                  .create_global::<ServerManager, _>(1, move |new_res, _| {
+                      new_res.implement(ServerManagerHandler { selection: selection.clone() },
+                                        None::<fn(_)>,
+                                        ());
+                  });
+        }
+
+        let child = {
+            let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+            let bytes_to_copy = bytes_to_copy.clone();
+            thread::spawn(move || {
+                let mut opts = Options::new();
+                opts.foreground(true)
+                    .serve_requests(ServeRequests::Only(2));
+                let sources =
+                    vec![MimeSource { source: Source::Bytes(bytes_to_copy.into()),
+                                      mime_type: MimeType::Specific("test".to_string()) }];
+                copy_internal(opts, sources, Some(socket_name))
+            })
+        };
+
+        for _ in 0..3 {
+            thread::sleep(Duration::from_millis(100));
+            server.answer();
+        }
+
+        // The first reader is gone before anything is written.
+        let (read, write) = pipe().unwrap();
+        drop(read);
+        selection.borrow().as_ref().unwrap().send("test".to_string(), write.as_raw_fd());
+        drop(write);
+        server.answer();
+
+        // The second reader still gets all of the data.
+        let (mut read, write) = pipe().unwrap();
+        selection.borrow().as_ref().unwrap().send("test".to_string(), write.as_raw_fd());
+        drop(write);
+        server.answer();
+
+        let mut contents = vec![];
+        read.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, bytes_to_copy);
+
+        child.join().unwrap().unwrap();
+    }
+}
+
+#[test]
+fn copy_reader() {
+    // Large enough to not be kept in memory.
+    let mut bytes_to_copy = vec![];
+    for i in 0..70000 {
+        bytes_to_copy.push((i % 256) as u8);
+    }
+
+    struct ServerManagerHandler {
+        selection: Rc<RefCell<Option<ServerSource>>>,
+    }
+
+    impl ServerManagerRequestHandler for ServerManagerHandler {
+        fn create_data_source(&mut self, _manager: ServerManager, id: NewResource<ServerSource>) {
+            id.implement_closure(|request, source| {
+                                     if let ServerSourceRequest::Offer { mime_type } = request {
+                                         source.as_ref()
+                                               .user_data::<RefCell<Vec<_>>>()
+                                               .unwrap()
+                                               .borrow_mut()
+                                               .push(mime_type);
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 RefCell::new(Vec::<String>::new()));
+        }
+
+        fn get_data_device(&mut self,
+                           _manager: ServerManager,
+                           id: NewResource<ServerDevice>,
+                           _seat: ServerSeat) {
+            let selection = self.selection.clone();
+            id.implement_closure(move |request, _| {
+                                     if let ServerDeviceRequest::SetSelection { source } = request {
+                                         *selection.borrow_mut() = source;
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 ());
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let selection = Rc::new(RefCell::new(None));
+    {
+        let selection = selection.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  new_res.implement(ServerManagerHandler { selection: selection.clone() },
+                                    None::<fn(_)>,
+                                    ());
+              });
+    }
+
+    let child = {
+        let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+        let bytes_to_copy = bytes_to_copy.clone();
+        thread::spawn(move || {
+            let mut opts = Options::new();
+            opts.foreground(true);
+            let reader = Box::new(io::Cursor::new(bytes_to_copy));
+            let sources = vec![MimeSource { source: Source::Reader(reader),
+                                            mime_type: MimeType::Specific("test".to_string()) }];
+            copy_internal(opts, sources, Some(socket_name))
+        })
+    };
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let (mut read, write) = pipe().unwrap();
+
+    if let Some(source) = selection.borrow().as_ref() {
+        source.send("test".to_string(), write.as_raw_fd());
+        drop(write);
+        source.cancelled();
+    }
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let mut contents = vec![];
+    read.read_to_end(&mut contents).unwrap();
+
+    child.join().unwrap().unwrap();
+
+    assert_eq!(contents.len(), bytes_to_copy.len());
+    assert_eq!(contents, bytes_to_copy);
+}
+
+#[test]
+fn copy_temp_dir_missing() {
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.display
+          .create_global::<ServerManager, _>(1, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true)
+            .temp_dir("/nonexistent/wl-clipboard-rs".into());
+        // Large enough to not be kept in memory.
+        let sources = vec![MimeSource { source: Source::Bytes(vec![0; 70000].into()),
+                                        mime_type: MimeType::Specific("test".to_string()) }];
+        copy_internal(opts, sources, Some(socket_name))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let error = child.join().unwrap().unwrap_err();
+    if let Error::TempCopy(SourceCreationError::TempDirCreateIn { path, .. }) = error {
+        assert_eq!(path, "/nonexistent/wl-clipboard-rs");
+    } else {
+        panic!("Invalid error: {:?}", error);
+    }
+}
+
+#[test]
+fn prepare_copy_sets_selection() {
+    struct ServerManagerHandler {
+        selection: Rc<RefCell<Option<ServerSource>>>,
+    }
+
+    impl ServerManagerRequestHandler for ServerManagerHandler {
+        fn create_data_source(&mut self, _manager: ServerManager, id: NewResource<ServerSource>) {
+            id.implement_dummy();
+        }
+
+        fn get_data_device(&mut self,
+                           _manager: ServerManager,
+                           id: NewResource<ServerDevice>,
+                           _seat: ServerSeat) {
+            let selection = self.selection.clone();
+            id.implement_closure(move |request, _| {
+                                     if let ServerDeviceRequest::SetSelection { source } = request {
+                                         *selection.borrow_mut() = source;
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 ());
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let selection = Rc::new(RefCell::new(None));
+    {
+        let selection = selection.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  new_res.implement(ServerManagerHandler { selection: selection.clone() },
+                                    None::<fn(_)>,
+                                    ());
+              });
+    }
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true);
+        let sources = vec![MimeSource { source: Source::Bytes([1, 3, 3, 7][..].into()),
+                                        mime_type: MimeType::Specific("test".to_string()) }];
+        // Don't serve, the selection must be set by the time prepare_copy returns.
+        prepare_copy_internal(opts, sources, Some(socket_name)).map(drop)
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    child.join().unwrap().unwrap();
+
+    assert!(selection.borrow().is_some());
+}
+
+#[test]
+fn prepare_copy_keep_temp_files() {
+    struct ServerManagerHandler {
+        selection: Rc<RefCell<Option<ServerSource>>>,
+    }
+
+    impl ServerManagerRequestHandler for ServerManagerHandler {
+        fn create_data_source(&mut self, _manager: ServerManager, id: NewResource<ServerSource>) {
+            id.implement_dummy();
+        }
+
+        fn get_data_device(&mut self,
+                           _manager: ServerManager,
+                           id: NewResource<ServerDevice>,
+                           _seat: ServerSeat) {
+            let selection = self.selection.clone();
+            id.implement_closure(move |request, _| {
+                                     if let ServerDeviceRequest::SetSelection { source } = request {
+                                         *selection.borrow_mut() = source;
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 ());
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let selection = Rc::new(RefCell::new(None));
+    {
+        let selection = selection.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  new_res.implement(ServerManagerHandler { selection: selection.clone() },
+                                    None::<fn(_)>,
+                                    ());
+              });
+    }
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let temp_dir_path = temp_dir.path().to_path_buf();
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true)
+            .temp_dir(temp_dir_path)
+            .keep_temp_files(true);
+        let data = vec![0; 1024 * 1024];
+        let sources = vec![MimeSource { source: Source::Bytes(data.into()),
+                                        mime_type: MimeType::Specific("test".to_string()) }];
+        prepare_copy_internal(opts, sources, Some(socket_name)).map(|prepared_copy| {
+                                                                   prepared_copy.temp_files()
+                                                               })
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    // The prepared copy is dropped by now, but the file must be kept.
+    let temp_files = child.join().unwrap().unwrap();
+    assert_eq!(temp_files.len(), 1);
+    assert!(temp_files[0].starts_with(temp_dir.path()));
+    assert_eq!(fs::read(&temp_files[0]).unwrap().len(), 1024 * 1024);
+    assert!(selection.borrow().is_some());
+}
+
+#[test]
+fn prepare_copy_seat_named_late() {
+    struct ServerManagerHandler {
+        selection: Rc<RefCell<Option<ServerSource>>>,
+    }
+
+    impl ServerManagerRequestHandler for ServerManagerHandler {
+        fn create_data_source(&mut self, _manager: ServerManager, id: NewResource<ServerSource>) {
+            id.implement_dummy();
+        }
+
+        fn get_data_device(&mut self,
+                           _manager: ServerManager,
+                           id: NewResource<ServerDevice>,
+                           seat: ServerSeat) {
+            // Only name the seat now, so the name arrives in a later roundtrip than the seat.
+            seat.name("late".to_string());
+
+            let selection = self.selection.clone();
+            id.implement_closure(move |request, _| {
+                                     if let ServerDeviceRequest::SetSelection { source } = request {
+                                         *selection.borrow_mut() = source;
+                                     }
+                                 },
+                                 None::<fn(_)>,
+                                 ());
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let selection = Rc::new(RefCell::new(None));
+    {
+        let selection = selection.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  new_res.implement(ServerManagerHandler { selection: selection.clone() },
+                                    None::<fn(_)>,
+                                    ());
+              });
+    }
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true)
+            .seat(Seat::Specific("late".to_string()));
+        let sources = vec![MimeSource { source: Source::Bytes([1, 3, 3, 7][..].into()),
+                                        mime_type: MimeType::Specific("test".to_string()) }];
+        prepare_copy_internal(opts, sources, Some(socket_name)).map(drop)
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    child.join().unwrap().unwrap();
+
+    assert!(selection.borrow().is_some());
+}
+
+#[test]
+fn dry_run_test() {
+    let mut opts = Options::new();
+    opts.additional_mime_types(vec!["test".to_string()]);
+    let html = b"<b>fish &amp; chips</b>";
+    let sources = vec![MimeSource { source: Source::Bytes(html[..].into()),
+                                    mime_type: MimeType::Specific("text/html".to_string()) },
+                       MimeSource { source: Source::Bytes([1, 3, 3, 7][..].into()),
+                                    mime_type: MimeType::Specific("other".to_string()) }];
+
+    let offers = dry_run(&opts, sources).unwrap();
+    let offer = |mime_type: &str, data, size| {
+        DryRunOffer { mime_type: mime_type.to_string(),
+                      data,
+                      size: Some(size) }
+    };
+    assert_eq!(offers,
+               [offer("text/html", 0, html.len() as u64),
+                offer("other", 1, 4),
+                offer("test", 0, html.len() as u64),
+                offer("text/plain;charset=utf-8", 2, 12),
+                offer("text/plain", 2, 12),
+                offer("STRING", 2, 12),
+                offer("UTF8_STRING", 2, 12),
+                offer("TEXT", 2, 12)]);
+}
+
+#[test]
+fn dry_run_additional_text_mime_types_first() {
+    let mut opts = Options::new();
+    opts.additional_text_mime_types_first(true);
+    let sources = vec![MimeSource { source: Source::Bytes(b"hello"[..].into()),
+                                    mime_type: MimeType::Specific("text/x-rust".to_string()) },
+                       MimeSource { source: Source::Bytes(b"world"[..].into()),
+                                    mime_type: MimeType::Specific("STRING".to_string()) }];
+
+    let offers = dry_run(&opts, sources).unwrap();
+    let mime_types = offers.iter().map(|x| &x.mime_type[..]).collect::<Vec<_>>();
+    assert_eq!(mime_types,
+               ["text/plain;charset=utf-8",
+                "text/plain",
+                "UTF8_STRING",
+                "TEXT",
+                "text/x-rust",
+                "STRING"]);
+}
+
+#[test]
+fn dry_run_removes_temp_files() {
+    let temp_dir = tempfile::tempdir().unwrap();
+
+    let mut opts = Options::new();
+    opts.temp_dir(temp_dir.path().to_path_buf());
+    let data = vec![0; 1024 * 1024];
+    let sources = vec![MimeSource { source: Source::Bytes(data.into()),
+                                    mime_type: MimeType::Specific("test".to_string()) }];
+
+    let offers = dry_run(&opts, sources).unwrap();
+    assert_eq!(offers.len(), 1);
+    assert_eq!(offers[0].size, Some(1024 * 1024));
+    assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn copy_paste_round_trip() {
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.create_clipboard_manager(2);
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let copy_socket_name = socket_name.clone();
+    let copy_child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true)
+            .serve_requests(ServeRequests::Only(1));
+        let sources = vec![MimeSource { source: Source::Bytes(b"hello"[..].into()),
+                                        mime_type: MimeType::Text }];
+        copy_internal(opts, sources, Some(copy_socket_name))
+    });
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    let paste_child = thread::spawn(move || {
+        let (mut read, mime_type) = get_contents_internal(PasteClipboardType::Regular,
+                                                          PasteSeat::Unspecified,
+                                                          PasteMimeType::Text,
+                                                          Some(socket_name))?;
+        let mut contents = vec![];
+        read.read_to_end(&mut contents).unwrap();
+        Ok::<_, PasteError>((mime_type, contents))
+    });
+
+    for _ in 0..4 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    let (mime_type, contents) = paste_child.join().unwrap().unwrap();
+    assert_eq!(mime_type, "text/plain;charset=utf-8");
+    assert_eq!(contents, b"hello");
+
+    // The only request was served, so the copy is done.
+    copy_child.join().unwrap().unwrap();
+}
+
+#[test]
+fn copy_paste_round_trip_binary_arguments() {
+    // Arguments joined the way wl-copy does it, with control and non-UTF-8 bytes.
+    let mut data = OsString::from_vec(b"a\0b\x01\x7f".to_vec());
+    data.push(" ");
+    data.push(OsString::from_vec(b"\xff\xfe\r\n\x1b".to_vec()));
+    let bytes_to_copy = data.into_vec();
+    assert_eq!(bytes_to_copy, b"a\0b\x01\x7f \xff\xfe\r\n\x1b");
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.create_clipboard_manager(2);
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let copy_socket_name = socket_name.clone();
+    let copy_child = {
+        let bytes_to_copy = bytes_to_copy.clone();
+        thread::spawn(move || {
+            let mut opts = Options::new();
+            opts.foreground(true)
+                .serve_requests(ServeRequests::Only(1));
+            let sources = vec![MimeSource { source: Source::Bytes(bytes_to_copy.into()),
+                                            mime_type: MimeType::Autodetect }];
+            copy_internal(opts, sources, Some(copy_socket_name))
+        })
+    };
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    let paste_child = thread::spawn(move || {
+        let (mut read, _) = get_contents_internal(PasteClipboardType::Regular,
+                                                  PasteSeat::Unspecified,
+                                                  PasteMimeType::Any,
+                                                  Some(socket_name))?;
+        let mut contents = vec![];
+        read.read_to_end(&mut contents).unwrap();
+        Ok::<_, PasteError>(contents)
+    });
+
+    for _ in 0..4 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    let contents = paste_child.join().unwrap().unwrap();
+    assert_eq!(contents, bytes_to_copy);
+
+    copy_child.join().unwrap().unwrap();
+}
+
+#[test]
+fn copy_bytes_leaves_stdin_alone() {
+    // Put some data in place of the standard input, which must still be there afterwards.
+    let (stdin_read, mut stdin_write) = pipe().unwrap();
+    stdin_write.write_all(b"untouched").unwrap();
+    drop(stdin_write);
+    let old_stdin = dup(STDIN_FILENO).unwrap();
+    dup2(stdin_read.as_raw_fd(), STDIN_FILENO).unwrap();
+    drop(stdin_read);
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.create_clipboard_manager(2);
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let copy_socket_name = socket_name.clone();
+    let copy_child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true)
+            .stream_stdin(true)
+            .serve_requests(ServeRequests::Only(1));
+        let sources = vec![MimeSource { source: Source::Bytes(b"hello"[..].into()),
+                                        mime_type: MimeType::Text }];
+        copy_internal(opts, sources, Some(copy_socket_name))
+    });
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    let paste_child = thread::spawn(move || {
+        let (mut read, _) = get_contents_internal(PasteClipboardType::Regular,
+                                                  PasteSeat::Unspecified,
+                                                  PasteMimeType::Text,
+                                                  Some(socket_name))?;
+        let mut contents = vec![];
+        read.read_to_end(&mut contents).unwrap();
+        Ok::<_, PasteError>(contents)
+    });
+
+    for _ in 0..4 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    assert_eq!(paste_child.join().unwrap().unwrap(), b"hello");
+    copy_child.join().unwrap().unwrap();
+
+    let mut stdin = [0; 16];
+    let len = read(STDIN_FILENO, &mut stdin).unwrap();
+    dup2(old_stdin, STDIN_FILENO).unwrap();
+    close(old_stdin).unwrap();
+    assert_eq!(&stdin[..len], b"untouched");
+}
+
+#[test]
+fn copy_guarded_is_owned() {
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.create_clipboard_manager(2);
+
+    let copy = |data: &'static [u8], socket_name| {
+        thread::spawn(move || {
+            let sources = vec![MimeSource { source: Source::Bytes(data.into()),
+                                            mime_type: MimeType::Text }];
+            copy_guarded_internal(Options::new(), sources, Some(socket_name))
+        })
+    };
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let first_child = copy(b"first", socket_name.clone());
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    let first_guard = first_child.join().unwrap().unwrap();
+    assert!(first_guard.is_owned());
+
+    let second_child = copy(b"second", socket_name);
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    let second_guard = second_child.join().unwrap().unwrap();
+
+    // The first copy's thread notices the loss on its own, so give it time.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while first_guard.is_owned() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(!first_guard.is_owned());
+    assert!(second_guard.is_owned());
+    first_guard.cancel().unwrap();
+}
+
+#[test]
+fn copy_guarded_is_owned_several_sources() {
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.create_clipboard_manager(2);
+
+    let copy = |clipboard, serve_requests, socket_name| {
+        thread::spawn(move || {
+            let mut opts = Options::new();
+            opts.clipboard(clipboard).serve_requests(serve_requests);
+            let sources = vec![MimeSource { source: Source::Bytes(b"data"[..].into()),
+                                            mime_type: MimeType::Text }];
+            copy_guarded_internal(opts, sources, Some(socket_name))
+        })
+    };
+    let mut copy_and_answer = |clipboard, serve_requests| {
+        let socket_name = server.socket_name.clone();
+        let child = copy(clipboard, serve_requests, socket_name);
+
+        for _ in 0..3 {
+            thread::sleep(Duration::from_millis(100));
+            server.answer();
+        }
+
+        child.join().unwrap().unwrap()
+    };
+
+    // Sources destroyed right away don't count, so this copy owns nothing.
+    let none_guard = copy_and_answer(ClipboardType::Both, ServeRequests::Only(0));
+    assert!(!none_guard.is_owned());
+
+    // Two sources, one for each clipboard.
+    let both_guard = copy_and_answer(ClipboardType::Both, ServeRequests::Unlimited);
+    assert!(both_guard.is_owned());
+
+    // Losing one of them keeps the other one.
+    let regular_guard = copy_and_answer(ClipboardType::Regular, ServeRequests::Unlimited);
+    thread::sleep(Duration::from_millis(200));
+    assert!(both_guard.is_owned());
+    assert!(regular_guard.is_owned());
+
+    // Losing both gives up the selection.
+    let primary_guard = copy_and_answer(ClipboardType::Primary, ServeRequests::Unlimited);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while both_guard.is_owned() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(!both_guard.is_owned());
+    assert!(regular_guard.is_owned());
+    assert!(primary_guard.is_owned());
+}
+
+#[test]
+fn copy_expire_after() {
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.create_clipboard_manager(2);
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let copy_socket_name = socket_name.clone();
+    let copy_child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true)
+            .expire_after(Duration::from_millis(300));
+        let sources = vec![MimeSource { source: Source::Bytes(b"hello"[..].into()),
+                                        mime_type: MimeType::Text }];
+        copy_internal(opts, sources, Some(copy_socket_name))
+    });
+
+    for _ in 0..6 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    // The selection expired without any requests.
+    copy_child.join().unwrap().unwrap();
+
+    let paste_child = thread::spawn(move || {
+        get_contents_internal(PasteClipboardType::Regular,
+                              PasteSeat::Unspecified,
+                              PasteMimeType::Text,
+                              Some(socket_name)).map(|_| ())
+    });
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    let error = paste_child.join().unwrap().unwrap_err();
+    if let PasteError::ClipboardEmpty = error {
+        // Pass
+    } else {
+        panic!("Invalid error: {:?}", error);
+    }
+}
+
+#[test]
+fn copy_idle_timeout() {
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.create_clipboard_manager(2);
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let copy_socket_name = socket_name.clone();
+    let copy_child = thread::spawn(move || {
+        let mut opts = Options::new();
+        opts.foreground(true)
+            .idle_timeout(Duration::from_millis(500));
+        let sources = vec![MimeSource { source: Source::Bytes(b"hello"[..].into()),
+                                        mime_type: MimeType::Text }];
+        copy_internal(opts, sources, Some(copy_socket_name))
+    });
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    let paste = |server: &mut TestServer| {
+        let socket_name = socket_name.clone();
+        let paste_child = thread::spawn(move || {
+            let (mut read, _) = get_contents_internal(PasteClipboardType::Regular,
+                                                      PasteSeat::Unspecified,
+                                                      PasteMimeType::Text,
+                                                      Some(socket_name))?;
+            let mut contents = vec![];
+            read.read_to_end(&mut contents).unwrap();
+            Ok::<_, PasteError>(contents)
+        });
+
+        for _ in 0..4 {
+            thread::sleep(Duration::from_millis(100));
+            server.answer();
+        }
+
+        paste_child.join().unwrap()
+    };
+
+    // Each paste restarts the timeout, so the second one is still served after more than the
+    // timeout since the selection was set.
+    assert_eq!(paste(&mut server).unwrap(), b"hello");
+    assert_eq!(paste(&mut server).unwrap(), b"hello");
+
+    for _ in 0..6 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    // Gave up the selection after nothing pasted for a while.
+    copy_child.join().unwrap().unwrap();
+
+    let error = paste(&mut server).unwrap_err();
+    if let PasteError::ClipboardEmpty = error {
+        // Pass
+    } else {
+        panic!("Invalid error: {:?}", error);
+    }
+}
+
+#[test]
+fn copy_replaced_paste_round_trip() {
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.create_clipboard_manager(2);
+
+    let copy = |data: &'static [u8], serve_requests, socket_name| {
+        thread::spawn(move || {
+            let mut opts = Options::new();
+            opts.foreground(true)
+                .clipboard(ClipboardType::Primary)
+                .serve_requests(serve_requests);
+            let sources = vec![MimeSource { source: Source::Bytes(data.into()),
+                                            mime_type: MimeType::Specific("test".to_string()) }];
+            copy_internal(opts, sources, Some(socket_name))
+        })
+    };
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let first_child = copy(b"first", ServeRequests::Unlimited, socket_name.clone());
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    let second_child = copy(b"second", ServeRequests::Only(1), socket_name.clone());
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    // The first copy is done once its selection is replaced.
+    first_child.join().unwrap().unwrap();
+
+    let paste_child = thread::spawn(move || {
+        let (mut read, _) = get_contents_internal(PasteClipboardType::Primary,
+                                                  PasteSeat::Unspecified,
+                                                  PasteMimeType::Specific("test"),
+                                                  Some(socket_name))?;
+        let mut contents = vec![];
+        read.read_to_end(&mut contents).unwrap();
+        Ok::<_, PasteError>(contents)
+    });
+
+    for _ in 0..4 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    assert_eq!(paste_child.join().unwrap().unwrap(), b"second");
+    second_child.join().unwrap().unwrap();
 }
@@ -1,7 +1,23 @@
-use std::{ffi::OsString, time::Duration};
+use std::{cell::RefCell, ffi::OsString, mem, rc::Rc, time::Duration};
 
+use nix::unistd::close;
+use wayland_protocols::wlr::unstable::data_control::v1::server::{
+    zwlr_data_control_device_v1::{
+        Request as ServerDeviceRequest, ZwlrDataControlDeviceV1 as ServerDevice,
+    },
+    zwlr_data_control_manager_v1::{
+        Request as ServerManagerRequest, ZwlrDataControlManagerV1 as ServerManager,
+    },
+    zwlr_data_control_offer_v1::{
+        Request as ServerOfferRequest, ZwlrDataControlOfferV1 as ServerOffer,
+    },
+    zwlr_data_control_source_v1::{
+        Request as ServerSourceRequest, ZwlrDataControlSourceV1 as ServerSource,
+    },
+};
 use wayland_server as ways;
 
+mod common;
 mod copy;
 mod paste;
 mod utils;
@@ -31,4 +47,169 @@ impl TestServer {
             .unwrap();
         self.display.flush_clients();
     }
+
+    /// Creates a data-control manager global that works like the clipboard of a compositor.
+    ///
+    /// The selections set by the clients are offered to all data devices, and receiving an offer
+    /// forwards the request to the data source. This allows for testing copying and pasting end
+    /// to end.
+    pub fn create_clipboard_manager(&mut self, version: u32) {
+        let selections = Rc::new(RefCell::new(Selections::default()));
+
+        self.display
+            .create_global::<ServerManager, _>(version, move |new_res, _| {
+                let selections = selections.clone();
+                new_res.implement_closure(move |request, _| match request {
+                                              ServerManagerRequest::CreateDataSource { id } => {
+                                                  create_source(id, selections.clone());
+                                              }
+                                              ServerManagerRequest::GetDataDevice { id, .. } => {
+                                                  create_device(id, selections.clone());
+                                              }
+                                              _ => {}
+                                          },
+                                          None::<fn(_)>,
+                                          ());
+            });
+    }
+}
+
+/// The state of the clipboard manager created by `TestServer::create_clipboard_manager()`.
+#[derive(Default)]
+struct Selections {
+    regular: Option<ServerSource>,
+    primary: Option<ServerSource>,
+    devices: Vec<ServerDevice>,
+}
+
+impl Selections {
+    fn add_device(&mut self, device: ServerDevice) {
+        send_offer(&device, self.regular.as_ref(), false);
+        if device.as_ref().version() >= 2 {
+            send_offer(&device, self.primary.as_ref(), true);
+        }
+
+        self.devices.push(device);
+    }
+
+    fn set(&mut self, source: Option<ServerSource>, primary: bool) {
+        let selection = if primary {
+            &mut self.primary
+        } else {
+            &mut self.regular
+        };
+
+        let old = mem::replace(selection, source);
+        if let Some(old) = old {
+            let replaced_by_itself = selection.as_ref()
+                                              .map(|x| x.as_ref().equals(old.as_ref()))
+                                              .unwrap_or(false);
+            if !replaced_by_itself && old.as_ref().is_alive() {
+                old.cancelled();
+            }
+        }
+
+        self.devices.retain(|device| device.as_ref().is_alive());
+        for device in &self.devices {
+            if !primary || device.as_ref().version() >= 2 {
+                send_offer(device, selection.as_ref(), primary);
+            }
+        }
+    }
+
+    fn source_destroyed(&mut self) {
+        // `equals()` is always false for dead resources, so drop whichever selection is dead.
+        //
+        // The destructor may run while the client is being disconnected, when sending it events
+        // would deadlock, so existing devices aren't notified. Devices created later get no offer.
+        for selection in &mut [&mut self.regular, &mut self.primary] {
+            if selection.as_ref()
+                        .map(|x| !x.as_ref().is_alive())
+                        .unwrap_or(false)
+            {
+                **selection = None;
+            }
+        }
+    }
+}
+
+fn create_source(id: self::ways::NewResource<ServerSource>, selections: Rc<RefCell<Selections>>) {
+    id.implement_closure(|request, source| {
+                             if let ServerSourceRequest::Offer { mime_type } = request {
+                                 source.as_ref()
+                                       .user_data::<RefCell<Vec<_>>>()
+                                       .unwrap()
+                                       .borrow_mut()
+                                       .push(mime_type);
+                             }
+                         },
+                         Some(move |_| selections.borrow_mut().source_destroyed()),
+                         RefCell::new(Vec::<String>::new()));
+}
+
+fn create_device(id: self::ways::NewResource<ServerDevice>, selections: Rc<RefCell<Selections>>) {
+    let device = {
+        let selections = selections.clone();
+        id.implement_closure(move |request, _| match request {
+                                 ServerDeviceRequest::SetSelection { source } => {
+                                     selections.borrow_mut().set(source, false);
+                                 }
+                                 ServerDeviceRequest::SetPrimarySelection { source } => {
+                                     selections.borrow_mut().set(source, true);
+                                 }
+                                 _ => {}
+                             },
+                             None::<fn(_)>,
+                             ())
+    };
+
+    selections.borrow_mut().add_device(device);
+}
+
+/// Sends an offer for the source as the selection of the device.
+fn send_offer(device: &ServerDevice, source: Option<&ServerSource>, primary: bool) {
+    let offer = source.map(|source| {
+        let offer = device.as_ref()
+                          .client()
+                          .unwrap()
+                          .create_resource::<ServerOffer>(device.as_ref().version())
+                          .unwrap();
+
+        let offer = {
+            let source = source.clone();
+            offer.implement_closure(move |request, _| {
+                                        if let ServerOfferRequest::Receive { mime_type, fd } =
+                                            request
+                                        {
+                                            if source.as_ref().is_alive() {
+                                                source.send(mime_type, fd);
+                                            }
+
+                                            // Sending duplicates the fd, so close ours for the
+                                            // reader to get EOF.
+                                            let _ = close(fd);
+                                        }
+                                    },
+                                    None::<fn(_)>,
+                                    ())
+        };
+
+        device.data_offer(&offer);
+        for mime_type in source.as_ref()
+                               .user_data::<RefCell<Vec<String>>>()
+                               .unwrap()
+                               .borrow()
+                               .iter()
+        {
+            offer.offer(mime_type.clone());
+        }
+
+        offer
+    });
+
+    if primary {
+        device.primary_selection(offer.as_ref());
+    } else {
+        device.selection(offer.as_ref());
+    }
 }
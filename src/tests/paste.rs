@@ -1,15 +1,19 @@
 use std::{
+    cell::{Cell, RefCell},
     collections::HashSet,
     ffi::OsString,
     io::{Read, Write},
     mem,
     os::unix::io::{FromRawFd, RawFd},
+    rc::Rc,
+    sync::mpsc::channel,
     thread,
     time::Duration,
 };
 
 use os_pipe::PipeWriter;
 use wayland_protocols::wlr::unstable::data_control::v1::server::{
+    zwlr_data_control_device_v1::ZwlrDataControlDeviceV1 as ServerDevice,
     zwlr_data_control_manager_v1::{
         Request as ServerManagerRequest, ZwlrDataControlManagerV1 as ServerManager,
     },
@@ -19,7 +23,7 @@ use wayland_protocols::wlr::unstable::data_control::v1::server::{
 };
 use wayland_server::protocol::wl_seat::WlSeat as ServerSeat;
 
-use crate::{paste::*, tests::TestServer};
+use crate::{connection::Connection, paste::*, tests::TestServer};
 
 #[test]
 fn get_mime_types_test() {
@@ -73,6 +77,248 @@ fn get_mime_types_test() {
     assert_eq!(mime_types, expected);
 }
 
+#[test]
+fn get_mime_types_with_connection_reused() {
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let binds = Rc::new(Cell::new(0));
+    let devices = Rc::new(Cell::new(0));
+    {
+        let binds = binds.clone();
+        let devices = devices.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  binds.set(binds.get() + 1);
+                  let devices = devices.clone();
+                  new_res.implement_closure(move |request, _| {
+                                                if let ServerManagerRequest::GetDataDevice { id,
+                                                                                            .. } =
+                                                    request
+                                                {
+                                                    devices.set(devices.get() + 1);
+                                                    let device = id.implement_dummy();
+                                                    let version = device.as_ref().version();
+                                                    let offer =
+                                                        device.as_ref()
+                                                              .client()
+                                                              .unwrap()
+                                                              .create_resource::<ServerOffer>(version)
+                                                              .unwrap()
+                                                              .implement_dummy();
+                                                    device.data_offer(&offer);
+                                                    offer.offer(format!("type{}", devices.get()));
+                                                    device.selection(Some(&offer));
+                                                }
+                                            },
+                                            None::<fn(_)>,
+                                            ());
+              });
+    }
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || -> Result<_, failure::Error> {
+        let mut connection = Connection::new_internal(Some(socket_name))?;
+        let first = get_mime_types_with_connection(&mut connection,
+                                                   ClipboardType::Regular,
+                                                   Seat::Unspecified)?;
+        let second = get_mime_types_with_connection(&mut connection,
+                                                    ClipboardType::Regular,
+                                                    Seat::Unspecified)?;
+        Ok((first, second))
+    });
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    let (first, second) = child.join().unwrap().unwrap();
+
+    assert_eq!(binds.get(), 1);
+    assert_eq!(devices.get(), 2);
+    assert_eq!(first.into_iter().collect::<Vec<_>>(), ["type1"]);
+    assert_eq!(second.into_iter().collect::<Vec<_>>(), ["type2"]);
+}
+
+#[test]
+fn get_mime_types_seat_index() {
+    let mut server = TestServer::new();
+    for &mime_type in &["first", "second"] {
+        server.display
+              .create_global::<ServerSeat, _>(6, move |new_res, _| {
+                  new_res.implement_closure(|_, _| {}, None::<fn(_)>, mime_type);
+              });
+    }
+    server.display
+          .create_global::<ServerManager, _>(1, |new_res, _| {
+              new_res.implement_closure(|request, _| match request {
+                                            ServerManagerRequest::GetDataDevice { id, seat } => {
+                                                let mime_type =
+                                                    *seat.as_ref().user_data::<&str>().unwrap();
+                                                let device = id.implement_dummy();
+                                                let offer =
+                                             device.as_ref()
+                                                   .client()
+                                                   .unwrap()
+                                                   .create_resource::<ServerOffer>(device.as_ref()
+                                                                                         .version())
+                                                   .unwrap()
+                                                   .implement_dummy();
+                                                device.data_offer(&offer);
+                                                offer.offer(mime_type.to_string());
+                                                device.selection(Some(&offer));
+                                            }
+                                            _ => unreachable!(),
+                                        },
+                                        None::<fn(_)>,
+                                        ());
+          });
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        get_mime_types_internal(ClipboardType::Regular, Seat::Specific("1"), Some(socket_name))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let mime_types = child.join().unwrap().unwrap();
+
+    let mut expected = HashSet::new();
+    expected.insert("second".to_string());
+    assert_eq!(mime_types, expected);
+}
+
+#[test]
+fn get_mime_types_seat_index_after_version_1() {
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(1, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    for &mime_type in &["first", "second"] {
+        server.display
+              .create_global::<ServerSeat, _>(6, move |new_res, _| {
+                  new_res.implement_closure(|_, _| {}, None::<fn(_)>, mime_type);
+              });
+    }
+    server.display
+          .create_global::<ServerManager, _>(1, |new_res, _| {
+              new_res.implement_closure(|request, _| match request {
+                                            ServerManagerRequest::GetDataDevice { id, seat } => {
+                                                let mime_type =
+                                                    *seat.as_ref().user_data::<&str>().unwrap();
+                                                let device = id.implement_dummy();
+                                                let offer =
+                                             device.as_ref()
+                                                   .client()
+                                                   .unwrap()
+                                                   .create_resource::<ServerOffer>(device.as_ref()
+                                                                                         .version())
+                                                   .unwrap()
+                                                   .implement_dummy();
+                                                device.data_offer(&offer);
+                                                offer.offer(mime_type.to_string());
+                                                device.selection(Some(&offer));
+                                            }
+                                            _ => unreachable!(),
+                                        },
+                                        None::<fn(_)>,
+                                        ());
+          });
+
+    // The version 1 seat is counted, so index 1 is the first bound seat.
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let mut connection = Connection::new_internal(Some(socket_name)).unwrap();
+        let first = get_mime_types_with_connection(&mut connection,
+                                                   ClipboardType::Regular,
+                                                   Seat::Specific("1"))?;
+        let version_1 = get_mime_types_with_connection(&mut connection,
+                                                       ClipboardType::Regular,
+                                                       Seat::Specific("0"));
+        Ok::<_, Error>((first, version_1))
+    });
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    let (first, version_1) = child.join().unwrap().unwrap();
+
+    let mut expected = HashSet::new();
+    expected.insert("first".to_string());
+    assert_eq!(first, expected);
+
+    match version_1 {
+        Err(Error::SeatVersionUnsupported) => {}
+        x => panic!("Invalid result: {:?}", x),
+    }
+}
+
+#[test]
+fn get_mime_types_seat_named_late() {
+    let mut server = TestServer::new();
+    for &mime_type in &["first", "second"] {
+        server.display
+              .create_global::<ServerSeat, _>(6, move |new_res, _| {
+                  new_res.implement_closure(|_, _| {}, None::<fn(_)>, mime_type);
+              });
+    }
+    server.display
+          .create_global::<ServerManager, _>(1, |new_res, _| {
+              new_res.implement_closure(|request, _| match request {
+                                            ServerManagerRequest::GetDataDevice { id, seat } => {
+                                                let mime_type =
+                                                    *seat.as_ref().user_data::<&str>().unwrap();
+                                                // Only name the seat now, so the name arrives in
+                                                // a later roundtrip than the seat.
+                                                seat.name(mime_type.to_string());
+                                                let device = id.implement_dummy();
+                                                let offer =
+                                             device.as_ref()
+                                                   .client()
+                                                   .unwrap()
+                                                   .create_resource::<ServerOffer>(device.as_ref()
+                                                                                         .version())
+                                                   .unwrap()
+                                                   .implement_dummy();
+                                                device.data_offer(&offer);
+                                                offer.offer(mime_type.to_string());
+                                                device.selection(Some(&offer));
+                                            }
+                                            _ => unreachable!(),
+                                        },
+                                        None::<fn(_)>,
+                                        ());
+          });
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        get_mime_types_internal(ClipboardType::Regular, Seat::Specific("second"), Some(socket_name))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let mime_types = child.join().unwrap().unwrap();
+
+    let mut expected = HashSet::new();
+    expected.insert("second".to_string());
+    assert_eq!(mime_types, expected);
+}
+
 #[test]
 fn get_mime_types_no_data_control() {
     let mut server = TestServer::new();
@@ -187,6 +433,45 @@ fn get_mime_types_empty_clipboard() {
     thread::sleep(Duration::from_millis(100));
     server.answer();
 
+    let mime_types = child.join().unwrap().unwrap();
+    assert!(mime_types.is_empty());
+}
+
+#[test]
+fn get_contents_no_selection_event() {
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.display
+          .create_global::<ServerManager, _>(1, |new_res, _| {
+              new_res.implement_closure(|request, _| match request {
+                                            // Never set since the compositor started, so no
+                                            // selection event at all.
+                                            ServerManagerRequest::GetDataDevice { id, .. } => {
+                                                id.implement_dummy();
+                                            }
+                                            _ => unreachable!(),
+                                        },
+                                        None::<fn(_)>,
+                                        ());
+          });
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        get_contents_internal(ClipboardType::Regular,
+                              Seat::Unspecified,
+                              MimeType::Any,
+                              Some(socket_name)).map(|_| ())
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
     let error = child.join().unwrap().unwrap_err();
     if let Error::ClipboardEmpty = error {
         // Pass
@@ -196,15 +481,7 @@ fn get_mime_types_empty_clipboard() {
 }
 
 #[test]
-fn get_contents_test() {
-    struct ServerOfferHandler;
-    impl ServerOfferRequestHandler for ServerOfferHandler {
-        fn receive(&mut self, _offer: ServerOffer, _mime_type: String, fd: RawFd) {
-            let mut write = unsafe { PipeWriter::from_raw_fd(fd) };
-            let _ = write.write_all(&[1, 3, 3, 7]);
-        }
-    }
-
+fn is_empty_test() {
     let mut server = TestServer::new();
     server.display
           .create_global::<ServerSeat, _>(6, |new_res, _| {
@@ -222,11 +499,9 @@ fn get_contents_test() {
                                                    .create_resource::<ServerOffer>(device.as_ref()
                                                                                          .version())
                                                    .unwrap()
-                                                   .implement(ServerOfferHandler,
-                                                              None::<fn(_)>,
-                                                              ());
+                                                   .implement_dummy();
                                                 device.data_offer(&offer);
-                                                offer.offer("application/octet-stream".to_string());
+                                                offer.offer("text/plain".to_string());
                                                 device.selection(Some(&offer));
                                             }
                                             _ => unreachable!(),
@@ -237,10 +512,7 @@ fn get_contents_test() {
 
     let socket_name = mem::replace(&mut server.socket_name, OsString::new());
     let child = thread::spawn(move || {
-        get_contents_internal(ClipboardType::Regular,
-                              Seat::Unspecified,
-                              MimeType::Any,
-                              Some(socket_name))
+        is_empty_internal(ClipboardType::Regular, Seat::Unspecified, Some(socket_name))
     });
 
     thread::sleep(Duration::from_millis(100));
@@ -249,19 +521,44 @@ fn get_contents_test() {
     thread::sleep(Duration::from_millis(100));
     server.answer();
 
+    let is_empty = child.join().unwrap().unwrap();
+    assert!(!is_empty);
+}
+
+#[test]
+fn is_empty_empty_clipboard() {
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.create_clipboard_manager(1);
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        is_empty_internal(ClipboardType::Regular, Seat::Unspecified, Some(socket_name))
+    });
+
     thread::sleep(Duration::from_millis(100));
     server.answer();
 
-    let (mut read, mime_type) = child.join().unwrap().unwrap();
-    assert_eq!(mime_type, "application/octet-stream");
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
 
-    let mut contents = vec![];
-    read.read_to_end(&mut contents).unwrap();
-    assert_eq!(contents, [1, 3, 3, 7]);
+    let is_empty = child.join().unwrap().unwrap();
+    assert!(is_empty);
 }
 
 #[test]
-fn get_contents_wrong_mime_type() {
+fn get_offer_test() {
+    struct ServerOfferHandler;
+    impl ServerOfferRequestHandler for ServerOfferHandler {
+        fn receive(&mut self, _offer: ServerOffer, mime_type: String, fd: RawFd) {
+            let mut write = unsafe { PipeWriter::from_raw_fd(fd) };
+            let _ = write.write_all(mime_type.as_bytes());
+        }
+    }
+
     let mut server = TestServer::new();
     server.display
           .create_global::<ServerSeat, _>(6, |new_res, _| {
@@ -279,9 +576,13 @@ fn get_contents_wrong_mime_type() {
                                                    .create_resource::<ServerOffer>(device.as_ref()
                                                                                          .version())
                                                    .unwrap()
-                                                   .implement_dummy();
+                                                   .implement(ServerOfferHandler,
+                                                              None::<fn(_)>,
+                                                              ());
                                                 device.data_offer(&offer);
-                                                offer.offer("application/octet-stream".to_string());
+                                                offer.offer("first".to_string());
+                                                offer.offer("second".to_string());
+                                                offer.offer("third".to_string());
                                                 device.selection(Some(&offer));
                                             }
                                             _ => unreachable!(),
@@ -292,25 +593,784 @@ fn get_contents_wrong_mime_type() {
 
     let socket_name = mem::replace(&mut server.socket_name, OsString::new());
     let child = thread::spawn(move || {
-        get_contents_internal(ClipboardType::Regular,
-                              Seat::Unspecified,
-                              MimeType::Specific("wrong"),
-                              Some(socket_name))
+        let mut offer = get_offer_internal(ClipboardType::Regular,
+                                           Seat::Unspecified,
+                                           Some(socket_name))?;
+        let mime_types = offer.mime_types().to_vec();
+        let (read, mime_type) = offer.receive(MimeType::Specific("third"))?;
+        let error = offer.receive(MimeType::Specific("fourth")).unwrap_err();
+        Ok::<_, Error>((mime_types, read, mime_type, error))
     });
 
-    thread::sleep(Duration::from_millis(100));
-    server.answer();
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
 
-    thread::sleep(Duration::from_millis(100));
-    server.answer();
+    let (mime_types, mut read, mime_type, error) = child.join().unwrap().unwrap();
+    assert_eq!(mime_types, ["first", "second", "third"]);
+    assert_eq!(mime_type, "third");
 
-    thread::sleep(Duration::from_millis(100));
-    server.answer();
+    let mut contents = vec![];
+    read.read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, b"third");
 
-    let error = child.join().unwrap().unwrap_err();
     if let Error::NoMimeType = error {
         // Pass
     } else {
         panic!("Invalid error: {:?}", error);
     }
 }
+
+#[test]
+fn get_contents_concat_test() {
+    struct ServerOfferHandler;
+    impl ServerOfferRequestHandler for ServerOfferHandler {
+        fn receive(&mut self, _offer: ServerOffer, mime_type: String, fd: RawFd) {
+            let mut write = unsafe { PipeWriter::from_raw_fd(fd) };
+            let _ = write.write_all(mime_type.as_bytes());
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.display
+          .create_global::<ServerManager, _>(1, |new_res, _| {
+              new_res.implement_closure(|request, _| match request {
+                                            ServerManagerRequest::GetDataDevice { id, .. } => {
+                                                let device = id.implement_dummy();
+                                                let offer =
+                                             device.as_ref()
+                                                   .client()
+                                                   .unwrap()
+                                                   .create_resource::<ServerOffer>(device.as_ref()
+                                                                                         .version())
+                                                   .unwrap()
+                                                   .implement(ServerOfferHandler,
+                                                              None::<fn(_)>,
+                                                              ());
+                                                device.data_offer(&offer);
+                                                offer.offer("first".to_string());
+                                                offer.offer("second".to_string());
+                                                offer.offer("third".to_string());
+                                                device.selection(Some(&offer));
+                                            }
+                                            _ => unreachable!(),
+                                        },
+                                        None::<fn(_)>,
+                                        ());
+          });
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        let result = get_contents_concat_internal(ClipboardType::Regular,
+                                                  Seat::Unspecified,
+                                                  &[MimeType::Specific("third"),
+                                                    MimeType::Pattern("f*")],
+                                                  b", ",
+                                                  Some(socket_name.clone()))?;
+        let error = get_contents_concat_internal(ClipboardType::Regular,
+                                                 Seat::Unspecified,
+                                                 &[MimeType::Specific("first"),
+                                                   MimeType::Specific("fourth")],
+                                                 b", ",
+                                                 Some(socket_name)).unwrap_err();
+        Ok::<_, Error>((result, error))
+    });
+
+    for _ in 0..8 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    let ((contents, mime_types), error) = child.join().unwrap().unwrap();
+    assert_eq!(contents, b"third, first");
+    assert_eq!(mime_types, ["third", "first"]);
+
+    if let Error::NoMimeType = error {
+        // Pass
+    } else {
+        panic!("Invalid error: {:?}", error);
+    }
+}
+
+#[test]
+fn get_contents_test() {
+    struct ServerOfferHandler;
+    impl ServerOfferRequestHandler for ServerOfferHandler {
+        fn receive(&mut self, _offer: ServerOffer, _mime_type: String, fd: RawFd) {
+            let mut write = unsafe { PipeWriter::from_raw_fd(fd) };
+            let _ = write.write_all(&[1, 3, 3, 7]);
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.display
+          .create_global::<ServerManager, _>(1, |new_res, _| {
+              new_res.implement_closure(|request, _| match request {
+                                            ServerManagerRequest::GetDataDevice { id, .. } => {
+                                                let device = id.implement_dummy();
+                                                let offer =
+                                             device.as_ref()
+                                                   .client()
+                                                   .unwrap()
+                                                   .create_resource::<ServerOffer>(device.as_ref()
+                                                                                         .version())
+                                                   .unwrap()
+                                                   .implement(ServerOfferHandler,
+                                                              None::<fn(_)>,
+                                                              ());
+                                                device.data_offer(&offer);
+                                                offer.offer("application/octet-stream".to_string());
+                                                device.selection(Some(&offer));
+                                            }
+                                            _ => unreachable!(),
+                                        },
+                                        None::<fn(_)>,
+                                        ());
+          });
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        get_contents_internal(ClipboardType::Regular,
+                              Seat::Unspecified,
+                              MimeType::Any,
+                              Some(socket_name))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let (mut read, mime_type) = child.join().unwrap().unwrap();
+    assert_eq!(mime_type, "application/octet-stream");
+
+    let mut contents = vec![];
+    read.read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, [1, 3, 3, 7]);
+}
+
+#[test]
+fn get_contents_primary() {
+    // Each offer sends its own name as the contents.
+    struct ServerOfferHandler(&'static str);
+    impl ServerOfferRequestHandler for ServerOfferHandler {
+        fn receive(&mut self, _offer: ServerOffer, _mime_type: String, fd: RawFd) {
+            let mut write = unsafe { PipeWriter::from_raw_fd(fd) };
+            let _ = write.write_all(self.0.as_bytes());
+        }
+    }
+
+    fn offer_both(device: ServerDevice) {
+        let client = device.as_ref().client().unwrap();
+        for &name in &["regular", "primary"] {
+            let offer = client.create_resource::<ServerOffer>(device.as_ref().version())
+                              .unwrap()
+                              .implement(ServerOfferHandler(name), None::<fn(_)>, ());
+            device.data_offer(&offer);
+            offer.offer("text/plain".to_string());
+            if name == "primary" {
+                device.primary_selection(Some(&offer));
+            } else {
+                device.selection(Some(&offer));
+            }
+        }
+    }
+
+    let get_contents = |clipboard| {
+        let mut server = TestServer::new();
+        server.display
+              .create_global::<ServerSeat, _>(6, |new_res, _| {
+                  new_res.implement_dummy();
+              });
+        server.display
+              .create_global::<ServerManager, _>(2, |new_res, _| {
+                  new_res.implement_closure(|request, _| match request {
+                                                ServerManagerRequest::GetDataDevice { id, .. } => {
+                                                    offer_both(id.implement_dummy());
+                                                }
+                                                _ => unreachable!(),
+                                            },
+                                            None::<fn(_)>,
+                                            ());
+              });
+
+        let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+        let child = thread::spawn(move || {
+            get_contents_internal(clipboard, Seat::Unspecified, MimeType::Any, Some(socket_name))
+        });
+
+        for _ in 0..3 {
+            thread::sleep(Duration::from_millis(100));
+            server.answer();
+        }
+
+        let (mut read, _) = child.join().unwrap().unwrap();
+        let mut contents = vec![];
+        read.read_to_end(&mut contents).unwrap();
+        contents
+    };
+
+    assert_eq!(get_contents(ClipboardType::Regular), b"regular");
+    assert_eq!(get_contents(ClipboardType::Primary), b"primary");
+}
+
+#[test]
+fn get_contents_pattern() {
+    struct ServerOfferHandler;
+    impl ServerOfferRequestHandler for ServerOfferHandler {
+        fn receive(&mut self, _offer: ServerOffer, _mime_type: String, fd: RawFd) {
+            let mut write = unsafe { PipeWriter::from_raw_fd(fd) };
+            let _ = write.write_all(&[1, 3, 3, 7]);
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.display
+          .create_global::<ServerManager, _>(1, |new_res, _| {
+              new_res.implement_closure(|request, _| match request {
+                                            ServerManagerRequest::GetDataDevice { id, .. } => {
+                                                let device = id.implement_dummy();
+                                                let offer =
+                                             device.as_ref()
+                                                   .client()
+                                                   .unwrap()
+                                                   .create_resource::<ServerOffer>(device.as_ref()
+                                                                                         .version())
+                                                   .unwrap()
+                                                   .implement(ServerOfferHandler,
+                                                              None::<fn(_)>,
+                                                              ());
+                                                device.data_offer(&offer);
+                                                offer.offer("text/plain".to_string());
+                                                offer.offer("image/png".to_string());
+                                                offer.offer("image/bmp".to_string());
+                                                device.selection(Some(&offer));
+                                            }
+                                            _ => unreachable!(),
+                                        },
+                                        None::<fn(_)>,
+                                        ());
+          });
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        get_contents_internal(ClipboardType::Regular,
+                              Seat::Unspecified,
+                              MimeType::Pattern("image/*"),
+                              Some(socket_name))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let (mut read, mime_type) = child.join().unwrap().unwrap();
+    // The first match in the offered order is requested.
+    assert_eq!(mime_type, "image/png");
+
+    let mut contents = vec![];
+    read.read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, [1, 3, 3, 7]);
+}
+
+#[test]
+fn get_contents_any_first_offered() {
+    struct ServerOfferHandler;
+    impl ServerOfferRequestHandler for ServerOfferHandler {
+        fn receive(&mut self, _offer: ServerOffer, _mime_type: String, fd: RawFd) {
+            let mut write = unsafe { PipeWriter::from_raw_fd(fd) };
+            let _ = write.write_all(&[1, 3, 3, 7]);
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.display
+          .create_global::<ServerManager, _>(1, |new_res, _| {
+              new_res.implement_closure(|request, _| match request {
+                                            ServerManagerRequest::GetDataDevice { id, .. } => {
+                                                let device = id.implement_dummy();
+                                                let offer =
+                                             device.as_ref()
+                                                   .client()
+                                                   .unwrap()
+                                                   .create_resource::<ServerOffer>(device.as_ref()
+                                                                                         .version())
+                                                   .unwrap()
+                                                   .implement(ServerOfferHandler,
+                                                              None::<fn(_)>,
+                                                              ());
+                                                device.data_offer(&offer);
+                                                offer.offer("image/png".to_string());
+                                                offer.offer("image/bmp".to_string());
+                                                device.selection(Some(&offer));
+                                            }
+                                            _ => unreachable!(),
+                                        },
+                                        None::<fn(_)>,
+                                        ());
+          });
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        get_contents_internal(ClipboardType::Regular,
+                              Seat::Unspecified,
+                              MimeType::Any,
+                              Some(socket_name))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let (mut read, mime_type) = child.join().unwrap().unwrap();
+    // Without text types, the first offered type is requested rather than the smallest one.
+    assert_eq!(mime_type, "image/png");
+
+    let mut contents = vec![];
+    read.read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, [1, 3, 3, 7]);
+}
+
+#[test]
+fn get_contents_into_test() {
+    struct ServerOfferHandler;
+    impl ServerOfferRequestHandler for ServerOfferHandler {
+        fn receive(&mut self, _offer: ServerOffer, _mime_type: String, fd: RawFd) {
+            let mut write = unsafe { PipeWriter::from_raw_fd(fd) };
+            let _ = write.write_all(&[1, 3, 3, 7]);
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.display
+          .create_global::<ServerManager, _>(1, |new_res, _| {
+              new_res.implement_closure(|request, _| match request {
+                                            ServerManagerRequest::GetDataDevice { id, .. } => {
+                                                let device = id.implement_dummy();
+                                                let offer =
+                                             device.as_ref()
+                                                   .client()
+                                                   .unwrap()
+                                                   .create_resource::<ServerOffer>(device.as_ref()
+                                                                                         .version())
+                                                   .unwrap()
+                                                   .implement(ServerOfferHandler,
+                                                              None::<fn(_)>,
+                                                              ());
+                                                device.data_offer(&offer);
+                                                offer.offer("application/octet-stream".to_string());
+                                                device.selection(Some(&offer));
+                                            }
+                                            _ => unreachable!(),
+                                        },
+                                        None::<fn(_)>,
+                                        ());
+          });
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        // The previous buffer contents must be replaced.
+        let mut buf = vec![4, 2];
+        get_contents_into_internal(ClipboardType::Regular,
+                                   Seat::Unspecified,
+                                   MimeType::Any,
+                                   &mut buf,
+                                   Some(socket_name)).map(|result| (result, buf))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let ((mime_type, len), buf) = child.join().unwrap().unwrap();
+    assert_eq!(mime_type, "application/octet-stream");
+    assert_eq!(len, 4);
+    assert_eq!(buf, [1, 3, 3, 7]);
+}
+
+// Pastes at most `max_bytes` bytes of the contents [1, 3, 3, 7].
+fn paste_limited(max_bytes: usize) -> (Vec<u8>, String, bool) {
+    struct ServerOfferHandler;
+    impl ServerOfferRequestHandler for ServerOfferHandler {
+        fn receive(&mut self, _offer: ServerOffer, _mime_type: String, fd: RawFd) {
+            let mut write = unsafe { PipeWriter::from_raw_fd(fd) };
+            let _ = write.write_all(&[1, 3, 3, 7]);
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.display
+          .create_global::<ServerManager, _>(1, |new_res, _| {
+              new_res.implement_closure(|request, _| match request {
+                                            ServerManagerRequest::GetDataDevice { id, .. } => {
+                                                let device = id.implement_dummy();
+                                                let offer =
+                                             device.as_ref()
+                                                   .client()
+                                                   .unwrap()
+                                                   .create_resource::<ServerOffer>(device.as_ref()
+                                                                                         .version())
+                                                   .unwrap()
+                                                   .implement(ServerOfferHandler,
+                                                              None::<fn(_)>,
+                                                              ());
+                                                device.data_offer(&offer);
+                                                offer.offer("application/octet-stream".to_string());
+                                                device.selection(Some(&offer));
+                                            }
+                                            _ => unreachable!(),
+                                        },
+                                        None::<fn(_)>,
+                                        ());
+          });
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        get_contents_limited_internal(ClipboardType::Regular,
+                                      Seat::Unspecified,
+                                      MimeType::Any,
+                                      max_bytes,
+                                      Some(socket_name))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    child.join().unwrap().unwrap()
+}
+
+#[test]
+fn get_contents_limited_test() {
+    let (contents, mime_type, truncated) = paste_limited(2);
+    assert_eq!(mime_type, "application/octet-stream");
+    assert_eq!(contents, [1, 3]);
+    assert!(truncated);
+
+    // Exactly at the limit is not truncated.
+    let (contents, _, truncated) = paste_limited(4);
+    assert_eq!(contents, [1, 3, 3, 7]);
+    assert!(!truncated);
+}
+
+#[test]
+fn get_contents_wrong_mime_type() {
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.display
+          .create_global::<ServerManager, _>(1, |new_res, _| {
+              new_res.implement_closure(|request, _| match request {
+                                            ServerManagerRequest::GetDataDevice { id, .. } => {
+                                                let device = id.implement_dummy();
+                                                let offer =
+                                             device.as_ref()
+                                                   .client()
+                                                   .unwrap()
+                                                   .create_resource::<ServerOffer>(device.as_ref()
+                                                                                         .version())
+                                                   .unwrap()
+                                                   .implement_dummy();
+                                                device.data_offer(&offer);
+                                                offer.offer("application/octet-stream".to_string());
+                                                device.selection(Some(&offer));
+                                            }
+                                            _ => unreachable!(),
+                                        },
+                                        None::<fn(_)>,
+                                        ());
+          });
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        get_contents_internal(ClipboardType::Regular,
+                              Seat::Unspecified,
+                              MimeType::Specific("wrong"),
+                              Some(socket_name))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let error = child.join().unwrap().unwrap_err();
+    if let Error::NoMimeType = error {
+        // Pass
+    } else {
+        panic!("Invalid error: {:?}", error);
+    }
+}
+
+#[test]
+fn watch_test() {
+    struct ServerOfferHandler;
+    impl ServerOfferRequestHandler for ServerOfferHandler {
+        fn receive(&mut self, _offer: ServerOffer, _mime_type: String, fd: RawFd) {
+            let mut write = unsafe { PipeWriter::from_raw_fd(fd) };
+            let _ = write.write_all(&[1, 3, 3, 7]);
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let client = Rc::new(RefCell::new(None));
+    {
+        let client = client.clone();
+        server.display
+              .create_global::<ServerManager, _>(1, move |new_res, _| {
+                  let client = client.clone();
+                  new_res.implement_closure(move |request, _| match request {
+                                                ServerManagerRequest::GetDataDevice { id, .. } => {
+                                                    let device = id.implement_dummy();
+                                                    *client.borrow_mut() = device.as_ref().client();
+
+                                                    let offer =
+                                                 device.as_ref()
+                                                       .client()
+                                                       .unwrap()
+                                                       .create_resource::<ServerOffer>(device.as_ref()
+                                                                                             .version())
+                                                       .unwrap()
+                                                       .implement(ServerOfferHandler,
+                                                                  None::<fn(_)>,
+                                                                  ());
+                                                    device.data_offer(&offer);
+                                                    offer.offer("application/octet-stream".to_string());
+                                                    device.selection(Some(&offer));
+                                                }
+                                                _ => unreachable!(),
+                                            },
+                                            None::<fn(_)>,
+                                            ());
+              });
+    }
+
+    let (tx, rx) = channel();
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        watch_internal(ClipboardType::Regular,
+                       Seat::Unspecified,
+                       MimeType::Any,
+                       |mut read, mime_type| {
+                           let mut contents = vec![];
+                           read.read_to_end(&mut contents).unwrap();
+                           tx.send((contents, mime_type)).unwrap();
+                       },
+                       Some(socket_name))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let (contents, mime_type) = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(mime_type, "application/octet-stream");
+    assert_eq!(contents, [1, 3, 3, 7]);
+
+    // Watching only stops on errors, like the compositor going away.
+    client.borrow().as_ref().unwrap().kill();
+    server.answer();
+
+    let error = child.join().unwrap().unwrap_err();
+    if let Error::WaylandCommunication(_) = error {
+        // Pass
+    } else {
+        panic!("Invalid error: {:?}", error);
+    }
+}
+
+#[test]
+fn watch_seat_removed() {
+    struct ServerOfferHandler;
+    impl ServerOfferRequestHandler for ServerOfferHandler {
+        fn receive(&mut self, _offer: ServerOffer, _mime_type: String, fd: RawFd) {
+            let mut write = unsafe { PipeWriter::from_raw_fd(fd) };
+            let _ = write.write_all(&[1, 3, 3, 7]);
+        }
+    }
+
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    let seat_global = server.display
+                            .create_global::<ServerSeat, _>(6, |new_res, _| {
+                                let seat = new_res.implement_dummy();
+                                seat.name("second".to_string());
+                            });
+    server.display
+          .create_global::<ServerManager, _>(1, |new_res, _| {
+              new_res.implement_closure(|request, _| match request {
+                                            ServerManagerRequest::GetDataDevice { id, .. } => {
+                                                let device = id.implement_dummy();
+                                                let offer =
+                                             device.as_ref()
+                                                   .client()
+                                                   .unwrap()
+                                                   .create_resource::<ServerOffer>(device.as_ref()
+                                                                                         .version())
+                                                   .unwrap()
+                                                   .implement(ServerOfferHandler,
+                                                              None::<fn(_)>,
+                                                              ());
+                                                device.data_offer(&offer);
+                                                offer.offer("application/octet-stream".to_string());
+                                                device.selection(Some(&offer));
+                                            }
+                                            _ => unreachable!(),
+                                        },
+                                        None::<fn(_)>,
+                                        ());
+          });
+
+    let (tx, rx) = channel();
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || {
+        watch_internal(ClipboardType::Regular,
+                       Seat::Specific("second"),
+                       MimeType::Any,
+                       |mut read, _| {
+                           let mut contents = vec![];
+                           read.read_to_end(&mut contents).unwrap();
+                           tx.send(contents).unwrap();
+                       },
+                       Some(socket_name))
+    });
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let contents = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(contents, [1, 3, 3, 7]);
+
+    // Removing the watched seat must be noticed rather than using the stale seat.
+    seat_global.destroy();
+    server.answer();
+
+    let error = child.join().unwrap().unwrap_err();
+    if let Error::SeatNotFound = error {
+        // Pass
+    } else {
+        panic!("Invalid error: {:?}", error);
+    }
+}
+
+#[test]
+fn best_text_mime_type_test() {
+    fn best(mime_types: &[&str]) -> Option<String> {
+        let mime_types = mime_types.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        best_text_mime_type(&mime_types).map(str::to_string)
+    }
+
+    let offered = ["TEXT",
+                   "STRING",
+                   "text/plain;charset=gbk",
+                   "text/plain",
+                   "text/html",
+                   "UTF8_STRING",
+                   "text/plain;charset=UTF-8",
+                   "image/png"];
+    assert_eq!(best(&offered), Some("text/plain;charset=UTF-8".to_string()));
+    assert_eq!(best(&offered[..6]), Some("UTF8_STRING".to_string()));
+    assert_eq!(best(&offered[..5]), Some("text/plain".to_string()));
+    assert_eq!(best(&offered[..3]), Some("text/plain;charset=gbk".to_string()));
+    assert_eq!(best(&offered[..2]), Some("STRING".to_string()));
+    assert_eq!(best(&offered[..1]), Some("TEXT".to_string()));
+    assert_eq!(best(&["text/html", "image/png"]), Some("text/html".to_string()));
+    assert_eq!(best(&["image/png"]), None);
+}
+
+#[test]
+fn matches_pattern_test() {
+    assert!(matches_pattern("*", "image/png"));
+    assert!(matches_pattern("*", ""));
+    assert!(matches_pattern("image/*", "image/png"));
+    assert!(matches_pattern("image/*", "image/"));
+    assert!(matches_pattern("Image/*", "image/PNG"));
+    assert!(matches_pattern("*/png", "image/png"));
+    assert!(matches_pattern("text/*;charset=utf-8", "text/plain;charset=utf-8"));
+    assert!(matches_pattern("*json*", "application/ld+json;charset=utf-8"));
+    assert!(matches_pattern("image/png", "image/png"));
+    assert!(matches_pattern("a*a", "aa"));
+
+    assert!(!matches_pattern("image/*", "text/plain"));
+    assert!(!matches_pattern("*/png", "image/pngx"));
+    assert!(!matches_pattern("image/png", "image/pngx"));
+    assert!(!matches_pattern("image/png", "image/pn"));
+    assert!(!matches_pattern("a*a", "a"));
+    assert!(!matches_pattern("*a*b", "ba"));
+}
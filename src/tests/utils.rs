@@ -1,11 +1,18 @@
-use std::{ffi::OsString, mem, thread, time::Duration};
+use std::{
+    ffi::OsString,
+    io::{self, Read},
+    mem,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
 
 use wayland_protocols::wlr::unstable::data_control::v1::server::zwlr_data_control_manager_v1::{
     Request as ServerManagerRequest, ZwlrDataControlManagerV1 as ServerManager,
 };
 use wayland_server::protocol::wl_seat::WlSeat as ServerSeat;
 
-use crate::{tests::TestServer, utils::*};
+use crate::{connection::Connection, tests::TestServer, utils::*};
 
 #[test]
 fn is_primary_selection_supported_test() {
@@ -159,3 +166,239 @@ fn is_primary_selection_supported_no_data_control() {
         panic!("Invalid error: {:?}", error);
     }
 }
+
+#[test]
+fn is_text_test() {
+    for &mime_type in &["text/plain",
+                        "text/plain;charset=utf-8",
+                        "text/html",
+                        "text/x-rust",
+                        "Text/Plain",
+                        "application/json",
+                        "application/json; charset=utf-8",
+                        "Application/XML",
+                        "STRING",
+                        "UTF8_STRING",
+                        "TEXT"]
+    {
+        assert!(is_text(mime_type), "{} should be text", mime_type);
+    }
+
+    for &mime_type in &["application/octet-stream",
+                        "application/jsonl",
+                        "image/png",
+                        "image/svg+xml",
+                        "text",
+                        "string",
+                        "utf8_string",
+                        ""]
+    {
+        assert!(!is_text(mime_type), "{} should not be text", mime_type);
+    }
+}
+
+#[test]
+fn trailing_newline_len_test() {
+    assert_eq!(trailing_newline_len(b"hello\n"), 1);
+    assert_eq!(trailing_newline_len(b"hello\r\n"), 2);
+    assert_eq!(trailing_newline_len(b"hello\r"), 1);
+    assert_eq!(trailing_newline_len(b"hello\n\n"), 1);
+    assert_eq!(trailing_newline_len(b"hello"), 0);
+    assert_eq!(trailing_newline_len(b""), 0);
+    assert_eq!(trailing_newline_len(b"\n"), 1);
+    assert_eq!(trailing_newline_len(b"\r\n"), 2);
+}
+
+#[test]
+fn strip_ansi_escapes_test() {
+    assert_eq!(strip_ansi_escapes(b""), b"");
+    assert_eq!(strip_ansi_escapes(b"plain\ttext\r\n"), b"plain\ttext\r\n");
+
+    // Colors.
+    assert_eq!(strip_ansi_escapes(b"\x1b[31mred\x1b[m \x1b[38;5;208mor\x1b[0m"), b"red or");
+    assert_eq!(strip_ansi_escapes(b"\x1b[38;2;255;0;0mrgb\x1b[39m"), b"rgb");
+
+    // Cursor movements and erasing.
+    assert_eq!(strip_ansi_escapes(b"50%\x1b[2K\x1b[1G100%\x1b[?25h"), b"50%100%");
+    assert_eq!(strip_ansi_escapes(b"\x1b7saved\x1b8\x1b(B"), b"saved");
+
+    // Hyperlinks, terminated by BEL and ST.
+    assert_eq!(strip_ansi_escapes(b"\x1b]8;;file:///a\x07a\x1b]8;;\x1b\\"), b"a");
+
+    // Incomplete sequences.
+    assert_eq!(strip_ansi_escapes(b"a\x1b[1;3"), b"a");
+    assert_eq!(strip_ansi_escapes(b"a\x1b"), b"a");
+    assert_eq!(strip_ansi_escapes(b"a\x1b[1\nb"), b"a\nb");
+
+    // UTF-8 is left alone.
+    assert_eq!(strip_ansi_escapes("\x1b[1mпривет\x1b[0m".as_bytes()), "привет".as_bytes());
+}
+
+#[test]
+fn counting_reader_test() {
+    let mut reader = CountingReader::new(&b"hello world"[..]);
+    assert_eq!(reader.bytes_read(), 0);
+
+    let mut buf = [0; 5];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+    assert_eq!(reader.bytes_read(), 5);
+
+    io::copy(&mut reader, &mut io::sink()).unwrap();
+    assert_eq!(reader.bytes_read(), 11);
+    assert!(reader.into_inner().is_empty());
+}
+
+#[test]
+fn html_to_plain_text_test() {
+    assert_eq!(html_to_plain_text("<b>hello</b> <i>world</i>"), "hello world");
+    assert_eq!(html_to_plain_text("<a href=\"x\">1 &lt; 2 &amp;&amp; 3 &gt; 2</a>"),
+               "1 < 2 && 3 > 2");
+    assert_eq!(html_to_plain_text("&quot;&apos;&#65;&#x42;&nbsp;"), "\"'AB\u{a0}");
+    assert_eq!(html_to_plain_text("fish & chips; &bogus;"), "fish & chips; &bogus;");
+    assert_eq!(html_to_plain_text("unterminated <tag"), "unterminated ");
+    assert_eq!(html_to_plain_text("plain"), "plain");
+}
+
+#[test]
+fn normalize_mime_type_test() {
+    assert_eq!(normalize_mime_type("text/plain"), "text/plain");
+    assert_eq!(normalize_mime_type("Text/Plain; Charset=UTF-8"),
+               "text/plain;charset=utf-8");
+    assert_eq!(normalize_mime_type("text/plain;format=Flowed"),
+               "text/plain;format=Flowed");
+    assert_eq!(normalize_mime_type("UTF8_STRING"), "UTF8_STRING");
+}
+
+#[test]
+fn mime_from_extension_test() {
+    let mime = |path| mime_from_extension(Path::new(path));
+    assert_eq!(mime("a.png").as_deref(), Some("image/png"));
+    assert_eq!(mime("a.jpg").as_deref(), Some("image/jpeg"));
+    assert_eq!(mime("a.jpeg").as_deref(), Some("image/jpeg"));
+    assert_eq!(mime("a.gif").as_deref(), Some("image/gif"));
+    assert_eq!(mime("a.webp").as_deref(), Some("image/webp"));
+    assert_eq!(mime("a.svg").as_deref(), Some("image/svg+xml"));
+    assert_eq!(mime("a.bmp").as_deref(), Some("image/bmp"));
+    assert_eq!(mime("a.tif").as_deref(), Some("image/tiff"));
+    assert_eq!(mime("a.tiff").as_deref(), Some("image/tiff"));
+    assert_eq!(mime("a.html").as_deref(), Some("text/html"));
+    assert_eq!(mime("a.htm").as_deref(), Some("text/html"));
+    assert_eq!(mime("a.txt").as_deref(), Some("text/plain"));
+    assert_eq!(mime("a.md").as_deref(), Some("text/markdown"));
+    assert_eq!(mime("a.json").as_deref(), Some("application/json"));
+    assert_eq!(mime("a.pdf").as_deref(), Some("application/pdf"));
+    assert_eq!(mime("/some/dir/A.PNG").as_deref(), Some("image/png"));
+    assert_eq!(mime("a.xyz"), None);
+    assert_eq!(mime("png"), None);
+    assert_eq!(mime(".png"), None);
+    assert_eq!(mime(""), None);
+}
+
+#[test]
+fn file_uri_test() {
+    let uri = |path| file_uri(Path::new(path));
+    assert_eq!(uri("/tmp/file.txt"), "file:///tmp/file.txt");
+    assert_eq!(uri("/tmp/a file"), "file:///tmp/a%20file");
+    assert_eq!(uri("/tmp/Ünïcödé"), "file:///tmp/%C3%9Cn%C3%AFc%C3%B6d%C3%A9");
+    assert_eq!(uri("/tmp/100%#?&+=:"), "file:///tmp/100%25%23%3F%26%2B%3D%3A");
+    assert_eq!(uri("/tmp/a-b_c.d~e"), "file:///tmp/a-b_c.d~e");
+    assert_eq!(uri("/tmp/new\nline"), "file:///tmp/new%0Aline");
+}
+
+#[test]
+fn uri_list_test() {
+    assert_eq!(uri_list(&[Path::new("/a b"), Path::new("/c")]),
+               "file:///a%20b\r\nfile:///c\r\n");
+    assert_eq!(uri_list::<&Path>(&[]), "");
+}
+
+#[test]
+fn parse_uri_list_test() {
+    let list = b"# Comment\r\n\
+                 file:///tmp/a%20file\r\n\
+                 \r\n\
+                 https://example.com/x\r\n\
+                 file://localhost/tmp/%C3%9Cn%C3%AFc%C3%B6d%C3%A9\n\
+                 FILE:///tmp/100%25\r\n\
+                 file:///tmp/bad%zz%4\r\n\
+                 file:relative";
+    assert_eq!(parse_uri_list(list),
+               [PathBuf::from("/tmp/a file"),
+                PathBuf::from("/tmp/Ünïcödé"),
+                PathBuf::from("/tmp/100%"),
+                PathBuf::from("/tmp/bad%zz%4")]);
+
+    let paths = [Path::new("/a b/c"), Path::new("/tmp/Ünïcödé")];
+    assert_eq!(parse_uri_list(uri_list(&paths).as_bytes()), paths);
+    assert!(parse_uri_list(b"").is_empty());
+}
+
+#[test]
+fn primary_and_seat_names_with_connection() {
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              let seat = new_res.implement_dummy();
+              seat.name("seat0".to_string());
+          });
+    server.display
+          .create_global::<ServerSeat, _>(1, |new_res, _| {
+              new_res.implement_dummy();
+          });
+    server.display
+          .create_global::<ServerManager, _>(2, |new_res, _| {
+              new_res.implement_closure(|request, _| match request {
+                                            ServerManagerRequest::GetDataDevice { id, .. } => {
+                                                let device = id.implement_dummy();
+                                                device.primary_selection(None);
+                                            }
+                                            _ => unreachable!(),
+                                        },
+                                        None::<fn(_)>,
+                                        ());
+          });
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || -> Result<_, failure::Error> {
+        let mut connection = Connection::new_internal(Some(socket_name))?;
+        let supported = is_primary_selection_supported_with_connection(&mut connection)?;
+        let names = get_seat_names_with_connection(&mut connection)?;
+        Ok((supported, names))
+    });
+
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        server.answer();
+    }
+
+    let (supported, names) = child.join().unwrap().unwrap();
+    assert!(supported);
+    assert_eq!(names, [Some("seat0".to_string()), None]);
+}
+
+#[test]
+fn get_seat_names_test() {
+    let mut server = TestServer::new();
+    server.display
+          .create_global::<ServerSeat, _>(6, |new_res, _| {
+              let seat = new_res.implement_dummy();
+              seat.name("seat0".to_string());
+          });
+    server.display
+          .create_global::<ServerSeat, _>(1, |new_res, _| {
+              new_res.implement_dummy();
+          });
+
+    let socket_name = mem::replace(&mut server.socket_name, OsString::new());
+    let child = thread::spawn(move || get_seat_names_internal(Some(socket_name)));
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    thread::sleep(Duration::from_millis(100));
+    server.answer();
+
+    let names = child.join().unwrap().unwrap();
+    assert_eq!(names, [Some("seat0".to_string()), None]);
+}
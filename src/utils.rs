@@ -3,8 +3,12 @@
 use std::{
     cell::{Cell, RefCell},
     ffi::{CString, OsString},
-    io,
-    os::unix::io::RawFd,
+    io::{self, Read},
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        io::RawFd,
+    },
+    path::{Path, PathBuf},
     process::abort,
     rc::Rc,
 };
@@ -13,35 +17,426 @@ use failure::Fail;
 use libc::{STDIN_FILENO, STDOUT_FILENO};
 use nix::{
     fcntl::{fcntl, FcntlArg, OFlag},
-    sys::wait::{waitpid, WaitStatus},
+    sys::{
+        signal::{sigprocmask, SigSet, SigmaskHow},
+        wait::{waitpid, WaitStatus},
+    },
     unistd::{close, dup2, execvp, fork, ForkResult},
 };
 use wayland_client::{
-    global_filter, protocol::wl_seat::WlSeat, ConnectError, Display, GlobalError, GlobalManager,
-    Interface, NewProxy,
+    protocol::wl_seat::WlSeat, ConnectError, Display, GlobalError, GlobalManager, Interface,
+    NewProxy,
 };
 use wayland_protocols::wlr::unstable::data_control::v1::client::zwlr_data_control_manager_v1::ZwlrDataControlManagerV1;
 
 use crate::{
-    handlers::{DataDeviceHandler, WlSeatHandler},
+    common::{create_devices, track_seats, DevicesError},
+    connection::Connection,
+    handlers::DataDeviceHandler,
     seat_data::SeatData,
 };
 
 /// Checks if the given MIME type represents plain text.
 ///
+/// The following MIME types count as text:
+///
+/// - `text/*`, for example `text/plain` or `text/html`,
+/// - `application/json` and `application/xml`,
+/// - the X11 text targets `STRING`, `UTF8_STRING` and `TEXT`.
+///
+/// The type and the subtype are matched case-insensitively and the parameters, like `charset`, are
+/// ignored. The X11 targets must match exactly.
+///
 /// # Examples
 ///
 /// ```
 /// use wl_clipboard_rs::utils::is_text;
 ///
 /// assert!(is_text("text/plain"));
+/// assert!(is_text("application/json; charset=utf-8"));
 /// assert!(!is_text("application/octet-stream"));
 /// ```
 pub fn is_text(mime_type: &str) -> bool {
     match mime_type {
         "TEXT" | "STRING" | "UTF8_STRING" => true,
-        x if x.starts_with("text/") => true,
-        _ => false,
+        _ => {
+            let base = mime_type.split(';').next().unwrap().trim().to_ascii_lowercase();
+            match &*base {
+                "application/json" | "application/xml" => true,
+                x => x.starts_with("text/"),
+            }
+        }
+    }
+}
+
+/// Returns the MIME type for a few common file extensions.
+///
+/// The extension is matched case-insensitively. Returns `None` for unknown extensions, in which
+/// case the MIME type can be detected from the contents or `application/octet-stream` used.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::Path;
+/// use wl_clipboard_rs::utils::mime_from_extension;
+///
+/// assert_eq!(mime_from_extension(Path::new("photo.JPG")).as_deref(), Some("image/jpeg"));
+/// assert_eq!(mime_from_extension(Path::new("archive.xyz")), None);
+/// ```
+pub fn mime_from_extension(path: &Path) -> Option<String> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let mime_type = match &*extension {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "bmp" => "image/bmp",
+        "tif" | "tiff" => "image/tiff",
+        "html" | "htm" => "text/html",
+        "txt" => "text/plain",
+        "md" => "text/markdown",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        _ => return None,
+    };
+
+    Some(mime_type.to_string())
+}
+
+/// Returns the `file://` URI of the given absolute path.
+///
+/// All bytes except for ASCII letters, digits, `-`, `.`, `_`, `~` and `/` are percent-encoded, so
+/// spaces and non-ASCII characters are encoded too.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::Path;
+/// use wl_clipboard_rs::utils::file_uri;
+///
+/// assert_eq!(file_uri(Path::new("/home/me/a file.txt")), "file:///home/me/a%20file.txt");
+/// ```
+pub fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for &byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// Builds a `text/uri-list` of the given absolute paths.
+///
+/// This is how file managers put files on the clipboard. Each path is converted with `file_uri()`
+/// and followed by a CRLF, as the format requires.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::Path;
+/// use wl_clipboard_rs::utils::uri_list;
+///
+/// let list = uri_list(&[Path::new("/tmp/a"), Path::new("/tmp/b")]);
+/// assert_eq!(list, "file:///tmp/a\r\nfile:///tmp/b\r\n");
+/// ```
+pub fn uri_list<P: AsRef<Path>>(paths: &[P]) -> String {
+    let mut list = String::new();
+    for path in paths {
+        list.push_str(&file_uri(path.as_ref()));
+        list.push_str("\r\n");
+    }
+    list
+}
+
+/// Parses a `text/uri-list` into the file paths it contains.
+///
+/// Empty lines and comments (lines starting with `#`) are skipped, and so are URIs with a scheme
+/// other than `file`. The paths are percent-decoded. A host name, like in
+/// `file://localhost/tmp/a`, is ignored.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::PathBuf;
+/// use wl_clipboard_rs::utils::parse_uri_list;
+///
+/// let paths = parse_uri_list(b"# A comment\r\nfile:///home/me/a%20file.txt\r\n");
+/// assert_eq!(paths, [PathBuf::from("/home/me/a file.txt")]);
+/// ```
+pub fn parse_uri_list(list: &[u8]) -> Vec<PathBuf> {
+    list.split(|&x| x == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.is_empty() && !line.starts_with(b"#"))
+        .filter_map(|line| {
+            // Compare the scheme case-insensitively.
+            if line.len() < 7 || !line[..7].eq_ignore_ascii_case(b"file://") {
+                return None;
+            }
+
+            // Skip the host name.
+            let rest = &line[7..];
+            let path = &rest[rest.iter().position(|&x| x == b'/')?..];
+            Some(PathBuf::from(OsString::from_vec(percent_decode(path))))
+        })
+        .collect()
+}
+
+/// Decodes the `%XX` escapes, leaving invalid ones as they are.
+fn percent_decode(input: &[u8]) -> Vec<u8> {
+    let hex = |x: u8| (x as char).to_digit(16).map(|x| x as u8);
+
+    let mut output = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] == b'%' {
+            if let (Some(high), Some(low)) = (input.get(i + 1).and_then(|&x| hex(x)),
+                                              input.get(i + 2).and_then(|&x| hex(x)))
+            {
+                output.push(high << 4 | low);
+                i += 3;
+                continue;
+            }
+        }
+
+        output.push(input[i]);
+        i += 1;
+    }
+    output
+}
+
+/// Normalizes the case of a MIME type.
+///
+/// The type, the subtype, the parameter names and the charset value are case-insensitive, so they
+/// are lowercased. Whitespace around the parameters is removed. Strings without a `/`, like the X11
+/// `STRING` and `UTF8_STRING` targets, are returned as is.
+pub(crate) fn normalize_mime_type(mime_type: &str) -> String {
+    if !mime_type.contains('/') {
+        return mime_type.to_string();
+    }
+
+    let mut parts = mime_type.split(';');
+    let mut normalized = parts.next().unwrap().trim().to_ascii_lowercase();
+
+    for parameter in parts {
+        normalized.push(';');
+
+        let parameter = parameter.trim();
+        match parameter.find('=') {
+            Some(i) => {
+                let name = parameter[..i].trim().to_ascii_lowercase();
+                let value = parameter[i + 1..].trim();
+                normalized.push_str(&name);
+                normalized.push('=');
+                if name == "charset" {
+                    normalized.push_str(&value.to_ascii_lowercase());
+                } else {
+                    normalized.push_str(value);
+                }
+            }
+            None => normalized.push_str(parameter),
+        }
+    }
+
+    normalized
+}
+
+/// Converts HTML into plain text by removing the tags and decoding the common entities.
+///
+/// The conversion is deliberately minimal: it's only used for offering a plain text version of
+/// copied HTML.
+pub(crate) fn html_to_plain_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(i) = rest.find(&['<', '&'][..]) {
+        text.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        if rest.starts_with('<') {
+            // Skip the tag. An unterminated tag swallows the rest of the input.
+            rest = rest.find('>').map(|end| &rest[end + 1..]).unwrap_or("");
+            continue;
+        }
+
+        if let Some(end) = rest.find(';') {
+            if let Some(c) = decode_html_entity(&rest[1..end]) {
+                text.push(c);
+                rest = &rest[end + 1..];
+                continue;
+            }
+        }
+
+        // Not an entity we know, keep the ampersand as is.
+        text.push('&');
+        rest = &rest[1..];
+    }
+
+    text.push_str(rest);
+    text
+}
+
+fn decode_html_entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        x if x.starts_with("#x") || x.starts_with("#X") => {
+            u32::from_str_radix(&x[2..], 16).ok().and_then(char::from_u32)
+        }
+        x if x.starts_with('#') => x[1..].parse().ok().and_then(char::from_u32),
+        _ => None,
+    }
+}
+
+/// Removes the trailing line terminator (`\n`, `\r\n` or `\r`) from `data`, if any.
+///
+/// Only a single line terminator is removed.
+///
+/// # Examples
+///
+/// ```
+/// use wl_clipboard_rs::utils::trim_trailing_newline;
+///
+/// let mut data = b"hello\r\n\n".to_vec();
+/// trim_trailing_newline(&mut data);
+/// assert_eq!(data, b"hello\r\n");
+/// ```
+pub fn trim_trailing_newline(data: &mut Vec<u8>) {
+    let length = data.len() - trailing_newline_len(data);
+    data.truncate(length);
+}
+
+/// Removes the ANSI escape sequences, like colors and cursor movements, from `data`.
+///
+/// Handles CSI sequences (including SGR ones setting colors), OSC sequences terminated by BEL or
+/// ST (like terminal hyperlinks) and the other escape sequences of the `ESC` + final byte form.
+/// An incomplete sequence at the end is removed as well.
+///
+/// # Examples
+///
+/// ```
+/// use wl_clipboard_rs::utils::strip_ansi_escapes;
+///
+/// let data = strip_ansi_escapes(b"\x1b[1;31merror\x1b[0m: oops");
+/// assert_eq!(data, b"error: oops");
+/// ```
+pub fn strip_ansi_escapes(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] != 0x1b {
+            result.push(data[i]);
+            i += 1;
+            continue;
+        }
+
+        i += 1;
+        match data.get(i) {
+            Some(b'[') => {
+                // Parameter and intermediate bytes, then the final byte.
+                i += 1;
+                while i < data.len() && (0x20..=0x3f).contains(&data[i]) {
+                    i += 1;
+                }
+                if i < data.len() && (0x40..=0x7e).contains(&data[i]) {
+                    i += 1;
+                }
+            }
+            Some(b']') => {
+                i += 1;
+                while i < data.len() {
+                    if data[i] == 0x07 {
+                        i += 1;
+                        break;
+                    }
+                    if data[i] == 0x1b && data.get(i + 1) == Some(&b'\\') {
+                        i += 2;
+                        break;
+                    }
+                    i += 1;
+                }
+            }
+            _ => {
+                // Intermediate bytes, then the final byte.
+                while i < data.len() && (0x20..=0x2f).contains(&data[i]) {
+                    i += 1;
+                }
+                if i < data.len() && (0x30..=0x7e).contains(&data[i]) {
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// Returns the length of the line terminator (`\n`, `\r\n` or `\r`) at the end of `data`.
+///
+/// Returns 0 if `data` doesn't end with a line terminator.
+pub(crate) fn trailing_newline_len(data: &[u8]) -> usize {
+    if data.ends_with(b"\r\n") {
+        2
+    } else if data.ends_with(b"\n") || data.ends_with(b"\r") {
+        1
+    } else {
+        0
+    }
+}
+
+/// A reader that counts the bytes read through it.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{self, Read};
+/// use wl_clipboard_rs::utils::CountingReader;
+///
+/// let mut reader = CountingReader::new(&b"hello"[..]);
+/// io::copy(&mut reader, &mut io::sink()).unwrap();
+/// assert_eq!(reader.bytes_read(), 5);
+/// ```
+#[derive(Debug)]
+pub struct CountingReader<R> {
+    inner: R,
+    bytes_read: u64,
+}
+
+impl<R: Read> CountingReader<R> {
+    /// Wraps the given reader.
+    #[inline]
+    pub fn new(inner: R) -> Self {
+        Self { inner,
+               bytes_read: 0 }
+    }
+
+    /// Returns the number of bytes read so far.
+    #[inline]
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Returns the wrapped reader.
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.bytes_read += count as u64;
+        Ok(count)
     }
 }
 
@@ -82,6 +477,9 @@ pub enum CopyDataError {
 ///
 /// If `from_fd` is `None`, the standard input is used as the data source.
 ///
+/// The copying is done by a child process which starts with no signals blocked, regardless of the
+/// signal mask of the calling thread.
+///
 /// If `wait` is `true`, this function returns after all data has been copied, otherwise it may
 /// return before all data has been copied.
 ///
@@ -116,6 +514,7 @@ pub fn copy_data(from_fd: Option<RawFd>, to_fd: RawFd, wait: bool) -> Result<(),
 
     // Don't allocate memory in the child process, it's not async-signal-safe.
     let cat = CString::new("cat").unwrap();
+    let no_signals = SigSet::empty();
 
     // Fork and exec cat.
     let fork_result = fork().map_err(CopyDataError::Fork)?;
@@ -144,6 +543,12 @@ pub fn copy_data(from_fd: Option<RawFd>, to_fd: RawFd, wait: bool) -> Result<(),
                 abort();
             }
 
+            // The signal mask survives exec, so unblock the signals the caller may have blocked
+            // (e.g. SIGTERM for a signalfd), otherwise they couldn't stop cat.
+            if sigprocmask(SigmaskHow::SIG_SETMASK, Some(&no_signals), None).is_err() {
+                abort();
+            }
+
             // Exec cat.
             if execvp(&cat, &[&cat]).is_err() {
                 abort();
@@ -242,18 +647,10 @@ pub(crate) fn is_primary_selection_supported_internal(
                                }.map_err(PrimarySelectionCheckError::WaylandConnection)?;
 
     let seats = Rc::new(RefCell::new(Vec::<WlSeat>::new()));
+    let seat_globals = Rc::new(RefCell::new(Vec::new()));
 
-    let seats_2 = seats.clone();
     let global_manager =
-        GlobalManager::new_with_cb(&display,
-                                   global_filter!([WlSeat, 2, move |seat: NewProxy<WlSeat>| {
-                                                      let seat_data =
-                                                          RefCell::new(SeatData::default());
-                                                      let seat =
-                                                          seat.implement(WlSeatHandler, seat_data);
-                                                      seats_2.borrow_mut().push(seat.clone());
-                                                      seat
-                                                  }]));
+        GlobalManager::new_with_cb(&display, track_seats(seats.clone(), seat_globals));
 
     // Retrieve the global interfaces.
     queue.sync_roundtrip()
@@ -293,3 +690,152 @@ pub(crate) fn is_primary_selection_supported_internal(
 
     Ok(supports_primary.get())
 }
+
+/// Checks if the compositor supports the primary selection, using an existing connection.
+///
+/// This is the same as `is_primary_selection_supported()`, except that `connection` is used
+/// instead of connecting to the compositor anew.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate wl_clipboard_rs;
+/// # extern crate failure;
+/// # use failure::Error;
+/// # fn foo() -> Result<(), Error> {
+/// use wl_clipboard_rs::{connection::Connection,
+///                       utils::is_primary_selection_supported_with_connection};
+///
+/// let mut connection = Connection::new()?;
+/// let supported = is_primary_selection_supported_with_connection(&mut connection)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn is_primary_selection_supported_with_connection(
+    connection: &mut Connection)
+    -> Result<bool, PrimarySelectionCheckError> {
+    // Version 1 does not support the primary clipboard.
+    if connection.data_control_version() < 2 {
+        return Ok(false);
+    }
+
+    match create_devices(&mut connection.common, true) {
+        Ok(()) => Ok(true),
+        Err(DevicesError::PrimarySelectionUnsupported) => Ok(false),
+        Err(DevicesError::NoSeats) => Err(PrimarySelectionCheckError::NoSeats),
+        Err(DevicesError::WaylandCommunication(err)) => {
+            Err(PrimarySelectionCheckError::WaylandCommunication(err))
+        }
+    }
+}
+
+/// Errors that can occur when listing the seats.
+#[derive(Fail, Debug)]
+pub enum SeatListError {
+    #[fail(display = "Couldn't connect to the Wayland compositor")]
+    WaylandConnection(#[cause] ConnectError),
+
+    #[fail(display = "Wayland compositor communication error")]
+    WaylandCommunication(#[cause] io::Error),
+}
+
+/// Returns the names of the existing seats.
+///
+/// The names can be used with `Seat::Specific`. Seats that didn't advertise a name (for example
+/// seats of version 1, which can't do that) are returned as `None`.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate wl_clipboard_rs;
+/// # use wl_clipboard_rs::utils::SeatListError;
+/// # fn foo() -> Result<(), SeatListError> {
+/// use wl_clipboard_rs::utils::get_seat_names;
+///
+/// for name in get_seat_names()? {
+///     println!("{}", name.as_deref().unwrap_or("<unnamed>"));
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[inline]
+pub fn get_seat_names() -> Result<Vec<Option<String>>, SeatListError> {
+    get_seat_names_internal(None)
+}
+
+pub(crate) fn get_seat_names_internal(socket_name: Option<OsString>)
+                                      -> Result<Vec<Option<String>>, SeatListError> {
+    // Connect to the Wayland compositor.
+    let (display, mut queue) = match socket_name {
+                                   Some(name) => Display::connect_to_name(name),
+                                   None => Display::connect_to_env(),
+                               }.map_err(SeatListError::WaylandConnection)?;
+
+    let seats = Rc::new(RefCell::new(Vec::<WlSeat>::new()));
+    let seat_globals = Rc::new(RefCell::new(Vec::new()));
+
+    let _global_manager =
+        GlobalManager::new_with_cb(&display, track_seats(seats.clone(), seat_globals.clone()));
+
+    // Retrieve the global interfaces, then the names of the seats bound along the way.
+    queue.sync_roundtrip()
+         .map_err(SeatListError::WaylandCommunication)?;
+    queue.sync_roundtrip()
+         .map_err(SeatListError::WaylandCommunication)?;
+
+    let names = seat_names(&seats.borrow(), &seat_globals.borrow());
+    Ok(names)
+}
+
+/// Returns the names of the existing seats, using an existing connection.
+///
+/// This is the same as `get_seat_names()`, except that `connection` is used instead of connecting
+/// to the compositor anew.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate wl_clipboard_rs;
+/// # extern crate failure;
+/// # use failure::Error;
+/// # fn foo() -> Result<(), Error> {
+/// use wl_clipboard_rs::{connection::Connection, utils::get_seat_names_with_connection};
+///
+/// let mut connection = Connection::new()?;
+/// for name in get_seat_names_with_connection(&mut connection)? {
+///     println!("{}", name.as_deref().unwrap_or("<unnamed>"));
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn get_seat_names_with_connection(connection: &mut Connection)
+                                      -> Result<Vec<Option<String>>, SeatListError> {
+    // Seats bound since the last roundtrip get their names during this one.
+    let common = &mut connection.common;
+    common.queue
+          .sync_roundtrip()
+          .map_err(SeatListError::WaylandCommunication)?;
+
+    let names = seat_names(&common.seats.borrow(), &common.seat_globals.borrow());
+    Ok(names)
+}
+
+// Returns the names of all seats in `seat_globals`, in the order that `Seat::Specific` indices
+// refer to.
+//
+// Only seats of version 2 and above are bound, the others are returned as `None`.
+fn seat_names(seats: &[WlSeat], seat_globals: &[u32]) -> Vec<Option<String>> {
+    seat_globals.iter()
+                .map(|&id| {
+                    seats.iter()
+                         .map(|seat| {
+                             seat.as_ref()
+                                 .user_data::<RefCell<SeatData>>()
+                                 .unwrap()
+                                 .borrow()
+                         })
+                         .find(|data| data.global_id == id)
+                         .and_then(|data| data.name.clone())
+                })
+                .collect()
+}